@@ -0,0 +1,30 @@
+// Standalone compute node: processes queued tasks without serving HTTP
+// Run several of these against the same Redis and shared results storage to scale processing
+use axum_kmap::{
+    config::Config,
+    services::RedisService,
+    shutdown,
+    worker,
+};
+use tokio::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    // Initialize basic tracing subscriber
+    tracing_subscriber::fmt::init();
+
+    // Same configuration as the web server, so paths and limits match
+    let config = Config::load().expect("Failed to load configuration");
+
+    let redis_service = RedisService::from_config(&config.redis)
+        .expect("Failed to connect to Redis");
+
+    let worker_pool = worker::spawn_worker_pool(redis_service, &config);
+
+    // Let running tasks finish, re-queueing whatever is left after the grace period
+    shutdown::shutdown_signal().await;
+    tracing::info!("Shutting down worker node");
+    worker_pool
+        .shutdown(Duration::from_secs(config.worker.shutdown_grace_period))
+        .await;
+}
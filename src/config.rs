@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
@@ -7,6 +8,10 @@ pub struct Config {
     pub worker: WorkerConfig,
     pub upload: UploadConfig,
     pub user: UserConfig,
+    #[serde(default)]
+    pub reference: ReferenceConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -23,22 +28,173 @@ pub struct RedisConfig {
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
 pub struct WorkerConfig {
+    pub in_process: bool,  // run workers inside the web server; turn off when kmap-worker nodes process tasks
     pub worker_count: usize,
     pub max_concurrent_tasks: usize,
+    pub heartbeat_ttl: u64,  // in seconds
+    pub supervisor_interval: u64,  // in seconds
+    pub max_attempts: u32,
+    pub max_task_duration: u64,  // in seconds
+    pub retry_base_delay: u64,  // in seconds
+    pub retry_max_delay: u64,  // in seconds
+    pub shutdown_grace_period: u64,  // in seconds
+    pub max_task_memory: u64,  // in bytes; submissions estimated to need more are rejected
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            in_process: true,
+            worker_count: 10,
+            max_concurrent_tasks: 8,
+            heartbeat_ttl: 30,
+            supervisor_interval: 10,
+            max_attempts: 3,
+            max_task_duration: 4 * 3600,
+            retry_base_delay: 30,
+            retry_max_delay: 1800,
+            shutdown_grace_period: 300,
+            max_task_memory: 8 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub result_ttl: u64,  // in seconds; finished tasks and cached results are kept this long after completion
+    pub janitor_interval: u64,  // in seconds
+    pub expiry_warning: u64,  // in seconds; the dashboard warns this long before results expire
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            result_ttl: 30 * 86400,
+            janitor_interval: 3600,
+            expiry_warning: 3 * 86400,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct UploadConfig {
     pub max_file_size: usize,  // 10MB in bytes
+    #[serde(default = "default_max_request_size")]
+    pub max_request_size: usize,  // in bytes; all files of one upload together, so a whole batch fits
     pub temp_dir: String,
     pub results_dir: String,
 }
 
+fn default_max_request_size() -> usize {
+    100 * 1024 * 1024
+}
+
+fn default_storage_quota() -> u64 {
+    1024 * 1024 * 1024
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct UserConfig {
     pub default_quota: u64,  // in seconds
+    #[serde(default = "default_storage_quota")]
+    pub default_storage_quota: u64,  // in bytes
     pub max_tasks_per_user: usize,
+    #[serde(default)]
+    pub max_running_tasks: Option<usize>,  // per user, unlimited when unset
+    #[serde(default)]
+    pub admins: Vec<String>,  // usernames allowed to use the /admin routes
+    #[serde(default)]
+    pub default_plan: Option<String>,  // plan of newly registered users
+    #[serde(default)]
+    pub plans: Vec<PlanConfig>,
+}
+
+// Limits of a named plan; admins assign plans to users
+#[derive(Debug, Deserialize, Clone)]
+pub struct PlanConfig {
+    pub name: String,
+    pub quota: u64,  // in seconds, per reset period
+    pub max_tasks: usize,
+    pub max_upload_size: usize,  // in bytes
+    pub storage_quota: u64,  // in bytes
+    #[serde(default)]
+    pub reset_period: Option<ResetPeriod>,  // used quota is never reset when unset
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetPeriod {
+    Weekly,   // Mondays at midnight UTC
+    Monthly,  // first of the month at midnight UTC
+}
+
+impl ResetPeriod {
+    // First reset strictly after the given time
+    pub fn next_reset(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        let midnight = Utc
+            .with_ymd_and_hms(after.year(), after.month(), after.day(), 0, 0, 0)
+            .unwrap();
+        match self {
+            ResetPeriod::Weekly => {
+                let days_left = 7 - after.weekday().num_days_from_monday() as i64;
+                midnight + Duration::days(days_left)
+            }
+            ResetPeriod::Monthly => {
+                let (year, month) = if after.month() == 12 {
+                    (after.year() + 1, 1)
+                } else {
+                    (after.year(), after.month() + 1)
+                };
+                Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap()
+            }
+        }
+    }
+}
+
+impl Config {
+    // Limits of a user's plan; users without a plan, or whose plan was removed from
+    // the configuration, get the defaults of [user] and [upload] without resets
+    pub fn plan(&self, name: Option<&str>) -> PlanConfig {
+        if let Some(name) = name {
+            match self.find_plan(name) {
+                Some(plan) => return plan.clone(),
+                None => tracing::warn!("Plan {} is not configured, using the defaults", name),
+            }
+        }
+        PlanConfig {
+            name: "default".into(),
+            quota: self.user.default_quota,
+            max_tasks: self.user.max_tasks_per_user,
+            max_upload_size: self.upload.max_file_size,
+            storage_quota: self.user.default_storage_quota,
+            reset_period: None,
+        }
+    }
+
+    pub fn find_plan(&self, name: &str) -> Option<&PlanConfig> {
+        self.user.plans.iter().find(|plan| plan.name == name)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ReferenceConfig {
+    #[serde(default)]
+    pub genomes: Vec<GenomeConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GenomeConfig {
+    pub name: String,
+    pub fasta_path: String,  // samtools faidx index expected at <fasta_path>.fai
+}
+
+impl ReferenceConfig {
+    pub fn find_genome(&self, name: &str) -> Option<&GenomeConfig> {
+        self.genomes.iter().find(|genome| genome.name == name)
+    }
 }
 
 impl Config {
@@ -50,4 +206,30 @@ impl Config {
 
         config.try_deserialize()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn weekly_reset_is_next_monday() {
+        // 2026-10-14 is a Wednesday
+        assert_eq!(ResetPeriod::Weekly.next_reset(at(2026, 10, 14, 15)), at(2026, 10, 19, 0));
+        assert_eq!(ResetPeriod::Weekly.next_reset(at(2026, 10, 18, 23)), at(2026, 10, 19, 0));
+        // A reset is strictly after the given time, even at midnight on a Monday
+        assert_eq!(ResetPeriod::Weekly.next_reset(at(2026, 10, 19, 0)), at(2026, 10, 26, 0));
+        assert_eq!(ResetPeriod::Weekly.next_reset(at(2026, 12, 30, 8)), at(2027, 1, 4, 0));
+    }
+
+    #[test]
+    fn monthly_reset_is_first_of_next_month() {
+        assert_eq!(ResetPeriod::Monthly.next_reset(at(2026, 10, 14, 15)), at(2026, 11, 1, 0));
+        assert_eq!(ResetPeriod::Monthly.next_reset(at(2026, 11, 1, 0)), at(2026, 12, 1, 0));
+        assert_eq!(ResetPeriod::Monthly.next_reset(at(2026, 12, 31, 23)), at(2027, 1, 1, 0));
+    }
+}
@@ -0,0 +1,20 @@
+use thiserror::Error;
+
+// Why a submission was refused by the limits of the user's plan
+#[derive(Error, Debug)]
+pub enum AdmissionError {
+    #[error("Limit of {0} queued or running tasks reached, wait for some to finish to submit new ones")]
+    TaskLimit(usize),
+
+    #[error("No processing quota left")]
+    QuotaExhausted,
+
+    #[error("Upload of {size} bytes is larger than the {limit} bytes allowed by your plan")]
+    UploadTooLarge { size: u64, limit: u64 },
+
+    #[error("File {filename} is larger than the {limit} bytes allowed per file")]
+    FileTooLarge { filename: String, limit: u64 },
+
+    #[error("Storage quota exceeded: results take {used} of {limit} bytes, delete some tasks to submit new ones")]
+    StorageQuotaExceeded { used: u64, limit: u64 },
+}
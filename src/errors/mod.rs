@@ -4,9 +4,11 @@ use thiserror::Error;
 // Make the response module public
 pub mod response;
 pub mod worker;
+pub mod admission;
 
 // Re-export commonly used types
-pub use worker::{WorkerError, WorkerResult};
+pub use worker::WorkerError;
+pub use admission::AdmissionError;
 
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -28,6 +30,9 @@ pub enum AppError {
 
     #[error("Worker error: {0}")]
     Worker(#[from] WorkerError),
+
+    #[error("Submission refused: {0}")]
+    Admission(#[from] AdmissionError),
 }
 
 // Custom result type
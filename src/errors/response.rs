@@ -5,6 +5,7 @@ use axum::{
 use urlencoding;
 use crate::errors::{
     AppError,
+    admission::AdmissionError,
     worker::WorkerError,
 };
 
@@ -43,10 +44,23 @@ impl IntoResponse for AppError {
 
             // Worker errors have specific status codes
             AppError::Worker(err) => convert_worker_error(err),
+
+            // Submissions over the plan's limits are refused
+            AppError::Admission(err) => convert_admission_error(err),
         }
     }
 }
 
+// Helper function to convert admission errors to responses
+fn convert_admission_error(err: AdmissionError) -> Response {
+    let status = match err {
+        AdmissionError::UploadTooLarge { .. } | AdmissionError::FileTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        AdmissionError::TaskLimit(_) => StatusCode::TOO_MANY_REQUESTS,
+        AdmissionError::QuotaExhausted | AdmissionError::StorageQuotaExceeded { .. } => StatusCode::FORBIDDEN,
+    };
+    (status, format!("Submission refused: {}", err)).into_response()
+}
+
 // Helper function to convert worker errors to responses
 fn convert_worker_error(err: WorkerError) -> Response {
     match err {
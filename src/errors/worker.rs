@@ -1,6 +1,7 @@
 use thiserror::Error;
 use redis::RedisError;
 use std::io;
+use crate::models::{TaskError, TaskErrorCode};
 
 #[derive(Error, Debug)]
 pub enum WorkerError {
@@ -27,6 +28,37 @@ pub enum WorkerError {
 
     #[error("Quota exceeded: user {0} has no remaining quota")]
     QuotaExceeded(String),
+
+    #[error("Task cancelled by user")]
+    Cancelled,
+
+    #[error("Task interrupted by shutdown")]
+    Interrupted,
+}
+
+impl WorkerError {
+    // Describe the failure for the task record; internal details stay in the logs
+    pub fn to_task_error(&self) -> TaskError {
+        match self {
+            WorkerError::Timeout(seconds) => TaskError::new(
+                TaskErrorCode::Timeout,
+                format!("Processing did not finish within the {} seconds reserved for it", seconds),
+            ),
+            WorkerError::QuotaExceeded(_) => TaskError::new(
+                TaskErrorCode::QuotaExceeded,
+                "You have no processing quota left",
+            ),
+            WorkerError::FileNotFound(_) => TaskError::new(
+                TaskErrorCode::FileNotFound,
+                "The uploaded input file is no longer available, please upload it again",
+            ),
+            WorkerError::InvalidKmer(msg) => TaskError::new(TaskErrorCode::InvalidKmer, msg.clone()),
+            WorkerError::Processing(msg) => TaskError::new(TaskErrorCode::Processing, msg.clone()),
+            WorkerError::TaskPanic(_) | WorkerError::Io(_) | WorkerError::Redis(_) | WorkerError::Cancelled | WorkerError::Interrupted => {
+                TaskError::new(TaskErrorCode::Internal, "An internal error occurred while processing the task")
+            }
+        }
+    }
 }
 
 pub type WorkerResult<T> = Result<T, WorkerError>;
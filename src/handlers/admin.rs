@@ -0,0 +1,153 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response, Json},
+};
+use tower_sessions::Session;
+use serde_json::json;
+use chrono::Utc;
+use crate::models::TaskStatus;
+use crate::services::RedisService;
+use crate::errors::{AppError, AppResult};
+use crate::config::Config;
+
+pub async fn list_dead_letter_tasks(
+    State((redis_service, config)): State<(RedisService, Config)>,
+    session: Session,
+) -> AppResult<Response> {
+    let admin = require_admin(&session, &config).await?;
+    tracing::info!("Admin {} listing dead-lettered tasks", admin);
+
+    let mut tasks = Vec::new();
+    for task_id in redis_service.get_dead_letter_tasks().await? {
+        match redis_service.get_task(&task_id).await? {
+            Some(task) => tasks.push(json!({
+                "task_id": task.task_id,
+                "user": task.user,
+                "filename": task.filename,
+                "attempts": task.attempts,
+                "error": task.error,
+                "submit_time": task.submission_time,
+                "complete_time": task.completion_time
+            })),
+            None => tracing::warn!("Dead-lettered task {} no longer exists", task_id),
+        }
+    }
+
+    Ok(Json(json!({ "tasks": tasks })).into_response())
+}
+
+pub async fn requeue_dead_letter_task(
+    State((redis_service, config)): State<(RedisService, Config)>,
+    session: Session,
+    Path(task_id): Path<String>,
+) -> AppResult<Response> {
+    let admin = require_admin(&session, &config).await?;
+    tracing::info!("Admin {} re-queueing dead-lettered task {}", admin, task_id);
+
+    let mut task = redis_service
+        .get_task(&task_id)
+        .await?
+        .ok_or_else(|| AppError::Task(format!("Task {} not found", task_id)))?;
+
+    // Dead-lettered tasks keep their input, unless it was cleaned up by hand
+    if !std::path::Path::new(&task.fasta_path).exists() {
+        return Err(AppError::Task(format!("Input of task {} is no longer available", task_id)));
+    }
+
+    // Removing it from the list claims the task, so it is re-queued only once
+    if !redis_service.remove_dead_letter_task(&task_id).await? {
+        return Err(AppError::Task(format!("Task {} is not in the dead-letter list", task_id)));
+    }
+
+    // Start over with a fresh set of attempts
+    task.status = TaskStatus::Queued;
+    task.attempts = 0;
+    task.error = None;
+    task.result = None;
+    task.start_time = None;
+    task.completion_time = None;
+    redis_service
+        .save_task(&task)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to save task {}: {}", task_id, e);
+            AppError::Redis(e)
+        })?;
+    // The task counts as active for its user again, even beyond the plan's limit
+    redis_service
+        .update_user(&task.user, |user| {
+            user.release_active_task(&task.task_id);
+            user.active_tasks.push(task.task_id.clone());
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update user {}: {}", task.user, e);
+            AppError::Redis(e)
+        })?;
+    redis_service
+        .queue_task(&task)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to queue task {}: {}", task_id, e);
+            AppError::Redis(e)
+        })?;
+
+    tracing::info!("Re-queued dead-lettered task {}", task_id);
+    Ok(Json(json!({
+        "task_id": task.task_id,
+        "status": task.status
+    })).into_response())
+}
+
+pub async fn assign_plan(
+    State((redis_service, config)): State<(RedisService, Config)>,
+    session: Session,
+    Path((username, plan_name)): Path<(String, String)>,
+) -> AppResult<Response> {
+    let admin = require_admin(&session, &config).await?;
+    tracing::info!("Admin {} assigning plan {} to user {}", admin, plan_name, username);
+
+    let plan = config
+        .find_plan(&plan_name)
+        .ok_or_else(|| AppError::Task(format!("Plan {} is not configured", plan_name)))?;
+
+    // Quota already used this period still counts against the new plan
+    let now = Utc::now();
+    let user = redis_service
+        .update_user(&username, |user| {
+            user.plan = Some(plan.name.clone());
+            user.quota_reset_at = plan.reset_period.map(|period| period.next_reset(now));
+            user.clone()
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to assign plan to user {}: {}", username, e);
+            AppError::Redis(e)
+        })?
+        .ok_or_else(|| AppError::Task(format!("User {} not found", username)))?;
+
+    tracing::info!("Assigned plan {} to user {}", plan_name, username);
+    Ok(Json(json!({
+        "user": user.username,
+        "plan": user.plan,
+        "quota": plan.quota,
+        "used_quota": user.used_quota,
+        "storage_quota": plan.storage_quota,
+        "quota_reset_at": user.quota_reset_at
+    })).into_response())
+}
+
+// Helper function to make sure the session belongs to one of the configured admins
+async fn require_admin(session: &Session, config: &Config) -> AppResult<String> {
+    let username = session
+        .get::<String>("user_session")
+        .await
+        .map_err(|e| AppError::Auth(format!("Session error: {}", e)))?
+        .ok_or_else(|| AppError::Auth("Not authenticated".into()))?;
+
+    if !config.user.admins.contains(&username) {
+        tracing::warn!("User {} tried to access an admin route", username);
+        return Err(AppError::Auth("Admin access required".into()));
+    }
+    Ok(username)
+}
@@ -5,6 +5,7 @@ use axum::{
 use tower_sessions::Session;
 use std::fs;
 use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::Utc;
 use crate::models::{LoginForm, RegisterForm, User};
 use crate::services::RedisService;
 use crate::errors::{AppError, AppResult};
@@ -12,7 +13,7 @@ use crate::config::Config;
 
 pub async fn serve_login_page() -> AppResult<Response> {
     let login_html = fs::read_to_string("templates/login.html")
-        .map_err(|e| AppError::File(e))?;
+        .map_err(AppError::File)?;
     Ok(Html(login_html).into_response())
 }
 
@@ -48,13 +49,13 @@ pub async fn handle_login(
 }
 
 pub async fn handle_register(
-    State((redis_service, _)): State<(RedisService, Config)>,
+    State((redis_service, config)): State<(RedisService, Config)>,
     Form(register_form): Form<RegisterForm>,
 ) -> AppResult<Response> {
     tracing::info!("Registration attempt for user: {}", register_form.username);
 
     // Check if user exists first
-    if let Some(_) = redis_service.get_user(&register_form.username).await? {
+    if redis_service.get_user(&register_form.username).await?.is_some() {
         tracing::warn!("Username already taken: {}", register_form.username);
         return Err(AppError::Auth("Username already taken".into()));
     }
@@ -72,12 +73,18 @@ pub async fn handle_register(
             AppError::Auth("Registration failed: password processing error".into())
         })?;
 
+    // New users start on the default plan
+    let plan = config.plan(config.user.default_plan.as_deref());
     let user = User {
         username: register_form.username.clone(),
         password_hash,
         tasks: Vec::new(),
-        quota: 36000,  // Set default quota 10hours
+        active_tasks: Vec::new(),
         used_quota: 0,
+        reserved_quota: 0,
+        used_storage: 0,
+        plan: config.user.default_plan.clone(),
+        quota_reset_at: plan.reset_period.map(|period| period.next_reset(Utc::now())),
     };
     
     // Save user to Redis
@@ -0,0 +1,286 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::{Html, IntoResponse, Response, Json},
+};
+use tower_sessions::Session;
+use std::{collections::HashMap, fs, path::Path as FilePath};
+use chrono::Utc;
+use serde_json::json;
+use crate::models::{BatchInfo, BatchStatus, TaskInfo, TaskStatus};
+use crate::kmap_algorithms::motif::MotifResults;
+use crate::services::{RedisService, archive};
+use crate::services::archive::{ArchiveEntry, EntrySource};
+use crate::errors::{AppError, AppResult};
+use crate::config::Config;
+use super::task::{archive_response, check_batch_admission, create_and_queue_task, remove_uploaded_file, DownloadQuery, UploadData, UploadedInput};
+
+// Helper function to create a batch, with one queued task per uploaded input
+// The whole batch is checked against the task limit first; if a task still fails, the tasks
+// created before it are kept and the remaining inputs are listed as skipped
+pub(super) async fn create_batch(
+    redis_service: &RedisService,
+    username: &str,
+    config: &Config,
+    upload_data: &UploadData,
+    inputs: Vec<UploadedInput>,
+) -> AppResult<String> {
+    let batch_id = uuid::Uuid::new_v4().to_string();
+    tracing::info!("Creating batch {} of {} tasks for user {}", batch_id, inputs.len(), username);
+
+    let user = redis_service
+        .get_user(username)
+        .await?
+        .ok_or_else(|| AppError::Auth("User not found".into()))?;
+    if let Err(e) = check_batch_admission(&user, &config.plan(user.plan.as_deref()), inputs.len()) {
+        tracing::warn!("Refused batch of {} tasks of user {}: {}", inputs.len(), username, e);
+        for input in &inputs {
+            remove_uploaded_file(&input.path);
+        }
+        return Err(e.into());
+    }
+
+    let mut batch = BatchInfo {
+        batch_id: batch_id.clone(),
+        user: username.to_string(),
+        task_ids: Vec::with_capacity(inputs.len()),
+        params: upload_data.form.clone(),
+        submission_time: Utc::now(),
+        skipped: Vec::new(),
+    };
+
+    let mut failure = None;
+    let mut inputs = inputs.into_iter();
+    for input in inputs.by_ref() {
+        let filename = input.filename.clone();
+        match create_and_queue_task(redis_service, username, config, upload_data, input, Some(&batch_id)).await {
+            Ok(task_id) => batch.task_ids.push(task_id),
+            Err(e) => {
+                tracing::error!("Failed to create task for {} in batch {}: {}", filename, batch_id, e);
+                batch.skipped.push(format!("{}: {}", filename, e));
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+    // Inputs after a failure, for example once the quota ran out, are never processed
+    for input in inputs {
+        batch.skipped.push(format!("{}: not submitted", input.filename));
+        remove_uploaded_file(&input.path);
+    }
+
+    // Without any task there is nothing to show, so the error goes to the user directly
+    if let Some(e) = failure.filter(|_| batch.task_ids.is_empty()) {
+        return Err(e);
+    }
+
+    redis_service.save_batch(&batch).await
+        .map_err(|e| {
+            tracing::error!("Failed to save batch {}: {}", batch_id, e);
+            AppError::Redis(e)
+        })?;
+
+    Ok(batch_id)
+}
+
+// Helper function to load a batch of the logged in user
+async fn get_user_batch(
+    redis_service: &RedisService,
+    session: &Session,
+    batch_id: &str,
+) -> AppResult<BatchInfo> {
+    let username = session
+        .get::<String>("user_session")
+        .await
+        .map_err(|e| AppError::Auth(format!("Session error: {}", e)))?
+        .ok_or_else(|| AppError::Auth("Not authenticated".into()))?;
+
+    // Users can only see their own batches
+    redis_service
+        .get_batch(batch_id)
+        .await?
+        .filter(|batch| batch.user == username)
+        .ok_or_else(|| {
+            tracing::warn!("Batch {} not found for user {}", batch_id, username);
+            AppError::Task(format!("Batch {} not found", batch_id))
+        })
+}
+
+// Helper function to load the tasks of a batch, skipping those deleted since
+async fn get_batch_tasks(redis_service: &RedisService, batch: &BatchInfo) -> AppResult<Vec<TaskInfo>> {
+    let mut tasks = Vec::with_capacity(batch.task_ids.len());
+    for task_id in &batch.task_ids {
+        match redis_service.get_task(task_id).await? {
+            Some(task) => tasks.push(task),
+            None => tracing::debug!("Task {} of batch {} was deleted", task_id, batch.batch_id),
+        }
+    }
+    Ok(tasks)
+}
+
+pub async fn view_batch(
+    State((redis_service, _)): State<(RedisService, Config)>,
+    session: Session,
+    Path(batch_id): Path<String>,
+) -> AppResult<Response> {
+    get_user_batch(&redis_service, &session, &batch_id).await?;
+
+    let template = fs::read_to_string("templates/batch.html")
+        .map_err(|e| {
+            tracing::error!("Failed to read batch template: {}", e);
+            AppError::File(e)
+        })?;
+
+    Ok(Html(template.replace("{{batch_id}}", &batch_id)).into_response())
+}
+
+pub async fn get_batch_status(
+    State((redis_service, _)): State<(RedisService, Config)>,
+    session: Session,
+    Path(batch_id): Path<String>,
+) -> AppResult<Response> {
+    tracing::debug!("Checking status for batch: {}", batch_id);
+
+    let batch = get_user_batch(&redis_service, &session, &batch_id).await?;
+    let tasks = get_batch_tasks(&redis_service, &batch).await?;
+
+    let status = BatchStatus::from_task_statuses(tasks.iter().map(|task| &task.status));
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for task in &tasks {
+        *counts.entry(format!("{:?}", task.status)).or_default() += 1;
+    }
+
+    let response = json!({
+        "batch_id": batch.batch_id,
+        "status": status,
+        "submit_time": batch.submission_time,
+        "total": tasks.len(),
+        "counts": counts,
+        "skipped": batch.skipped,
+        "tasks": tasks.iter().map(|task| json!({
+            "task_id": task.task_id,
+            "filename": task.filename,
+            "status": task.status,
+            "error": task.error,
+            "complete_time": task.completion_time,
+        })).collect::<Vec<_>>(),
+    });
+
+    Ok(Json(response).into_response())
+}
+
+pub async fn download_batch(
+    State((redis_service, _)): State<(RedisService, Config)>,
+    session: Session,
+    Path(batch_id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+) -> AppResult<Response> {
+    tracing::info!("Starting download for batch: {}", batch_id);
+    let format = query.archive_format()?;
+
+    let batch = get_user_batch(&redis_service, &session, &batch_id).await?;
+    let tasks: Vec<TaskInfo> = get_batch_tasks(&redis_service, &batch)
+        .await?
+        .into_iter()
+        .filter(|task| matches!(task.status, TaskStatus::Completed))
+        .collect();
+    if tasks.is_empty() {
+        return Err(AppError::Task(format!("Batch {} has no completed tasks yet", batch_id)));
+    }
+
+    // Each task's results under its own directory, next to the summary
+    let root = format!("batch_{}", batch_id);
+    let samples: Vec<(String, &TaskInfo)> = tasks.iter().map(|task| (sample_name(task), task)).collect();
+    let mut entries = batch_entries(&samples, &root)?;
+    entries.push(ArchiveEntry {
+        name: format!("{}/motif_summary.tsv", root),
+        source: EntrySource::Data(motif_summary(&samples).into_bytes()),
+    });
+    let manifest = json!({
+        "batch_id": batch.batch_id,
+        "submission_time": batch.submission_time,
+        "samples": samples.iter().map(|(sample, task)| json!({
+            "sample": sample,
+            "task_id": task.task_id,
+            "filename": task.filename,
+        })).collect::<Vec<_>>(),
+    });
+
+    let filename = format!("batch_{}.{}", batch_id, format.extension());
+    let response = archive_response(format, entries, manifest, format!("{}/manifest.json", root), &filename)?;
+    tracing::info!("Successfully prepared download response for batch: {}", batch_id);
+    Ok(response)
+}
+
+// Helper function to name a task's directory in a batch download
+fn sample_name(task: &TaskInfo) -> String {
+    let stem = FilePath::new(&task.filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("sample");
+    format!("{}_{}", stem, &task.task_id[..8.min(task.task_id.len())])
+}
+
+// Helper function to list the results of every sample of a batch, each under <root>/<sample>
+fn batch_entries(samples: &[(String, &TaskInfo)], root: &str) -> AppResult<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    for (sample, task) in samples {
+        let task_entries = archive::collect_files(FilePath::new(&task.result_path), &format!("{}/{}", root, sample))
+            .map_err(|e| {
+                tracing::error!("Failed to gather results of task {}: {}", task.task_id, e);
+                AppError::File(e)
+            })?;
+        entries.extend(task_entries);
+    }
+    Ok(entries)
+}
+
+// Helper function to build the cross-sample table of top motifs
+// samples_with_consensus counts the samples in which the same consensus was found
+fn motif_summary(samples: &[(String, &TaskInfo)]) -> String {
+    let mut rows = Vec::new();
+    for (sample, task) in samples {
+        let results_path = FilePath::new(&task.result_path).join("results.json");
+        let motif_results: MotifResults = match fs::read(&results_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        {
+            Some(motif_results) => motif_results,
+            None => {
+                tracing::warn!("No readable motif results for task {}", task.task_id);
+                continue;
+            }
+        };
+
+        for (index, motif) in motif_results.motifs.iter().enumerate() {
+            let sites = motif.refinement.as_ref().map_or(motif.seed_count as usize, |em| em.sites);
+            rows.push((sample.as_str(), task.task_id.as_str(), index + 1, motif.clone(), sites));
+        }
+    }
+
+    let mut samples_per_consensus: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (sample, _, _, motif, _) in &rows {
+        let samples = samples_per_consensus.entry(motif.consensus.as_str()).or_default();
+        if !samples.contains(sample) {
+            samples.push(sample);
+        }
+    }
+
+    let mut summary = String::from(
+        "sample\ttask_id\trank\tmotif\tconsensus\tseed\tseed_count\tsites\tsamples_with_consensus\n"
+    );
+    for (sample, task_id, rank, motif, sites) in &rows {
+        summary.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            sample,
+            task_id,
+            rank,
+            motif.label(*rank),
+            motif.consensus,
+            motif.seed,
+            motif.seed_count,
+            sites,
+            samples_per_consensus[motif.consensus.as_str()].len()
+        ));
+    }
+    summary
+}
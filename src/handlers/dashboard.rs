@@ -3,12 +3,13 @@ use axum::{
     response::{Html, IntoResponse, Response, Redirect},
 };
 use tower_sessions::Session;
-use tokio::fs::remove_dir_all;
-use crate::models::{User, TaskInfo};
+use chrono::Utc;
+use crate::models::{TaskInfo, TaskStatus};
 use crate::services::RedisService;
 use crate::errors::{AppError, AppResult};
-use tracing;
 use crate::config::Config;
+use crate::worker::remove_task;
+use super::task::get_user_task;
 
 pub async fn serve_user_dashboard(
     State((redis_service, config)): State<(RedisService, Config)>,
@@ -44,7 +45,7 @@ pub async fn serve_user_dashboard(
     }
     
     // Sort tasks by submission time (newest first)
-    tasks_info.sort_by(|a, b| b.submission_time.cmp(&a.submission_time));
+    tasks_info.sort_by_key(|task| std::cmp::Reverse(task.submission_time));
     
     // Read and render the template
     let dashboard_html = std::fs::read_to_string("templates/user_dashboard.html")
@@ -54,15 +55,50 @@ pub async fn serve_user_dashboard(
         })?;
     
     // Replace template variables
+    let now = Utc::now();
     let tasks_html = tasks_info.iter().map(|task| {
+        // Only tasks that have not finished yet can be cancelled
+        let cancel_button = if matches!(task.status, TaskStatus::Queued | TaskStatus::Processing) {
+            format!(r#"<a href="/cancel/{}" class="cancel-btn">Cancel</a>"#, task.task_id)
+        } else {
+            String::new()
+        };
+        // Say why a task failed right under its status
+        let error_detail = task.error.as_ref().map_or(String::new(), |error| {
+            format!(
+                r#"<div class="error-detail">{:?}: {}</div>"#,
+                error.code,
+                escape_html(&error.message)
+            )
+        });
+        // Results reused from an identical earlier task
+        let cache_detail = if task.cache.as_ref().is_some_and(|cache| cache.hit) {
+            r#"<div class="task-note">Reused cached results</div>"#
+        } else {
+            ""
+        };
+        // Link tasks submitted together to their batch overview
+        let batch_detail = task.batch_id.as_ref().map_or(String::new(), |batch_id| {
+            format!(r#"<div class="task-note"><a href="/batch/{}">Part of a batch</a></div>"#, batch_id)
+        });
+        // Warn before the janitor deletes the results
+        let expiry_detail = task.expires_at(config.retention.result_ttl)
+            .filter(|expires_at| (*expires_at - now).num_seconds() <= config.retention.expiry_warning as i64)
+            .map_or(String::new(), |expires_at| {
+                format!(
+                    r#"<div class="task-note expiry-warning">Results will be deleted on {}</div>"#,
+                    expires_at.format("%Y-%m-%d %H:%M:%S")
+                )
+            });
         format!(
             r#"<tr>
                 <td>{}</td>
                 <td>{}</td>
                 <td>{}</td>
-                <td>{:?}</td>
+                <td>{:?}{}{}{}{}</td>
                 <td class="action-cell">
                     <a href="/process/{}" class="view-btn">View Results</a>
+                    {}
                     <a href="/delete/{}" class="delete-btn">Delete</a>
                 </td>
             </tr>"#,
@@ -70,18 +106,34 @@ pub async fn serve_user_dashboard(
             task.submission_time.format("%Y-%m-%d %H:%M:%S"),
             task.completion_time.map_or("Pending".to_string(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
             task.status,
+            error_detail,
+            cache_detail,
+            batch_detail,
+            expiry_detail,
             task.task_id,
+            cancel_button,
             task.task_id
         )
     }).collect::<Vec<_>>().join("\n");
     
+    let plan = config.plan(user.plan.as_deref());
+    let quota_reset = match (plan.reset_period, user.quota_reset_at) {
+        (Some(_), Some(reset_at)) => format!(", resets on {}", reset_at.format("%Y-%m-%d")),
+        _ => String::new(),
+    };
+
     let dashboard_html = dashboard_html
         .replace("{{username}}", &username)
+        .replace("{{plan}}", &plan.name)
+        .replace("{{quota_reset}}", &quota_reset)
         .replace("{{tasks}}", &tasks_html)
         .replace("{{quota_used}}", &user.used_quota.to_string())
-        .replace("{{quota_total}}", &user.quota.to_string())
-        .replace("{{task_count}}", &user.tasks.len().to_string())
-        .replace("{{max_tasks}}", &config.user.max_tasks_per_user.to_string());
+        .replace("{{quota_total}}", &plan.quota.to_string())
+        .replace("{{quota_reserved}}", &user.reserved_quota.to_string())
+        .replace("{{storage_used}}", &format_megabytes(user.used_storage))
+        .replace("{{storage_total}}", &format_megabytes(plan.storage_quota))
+        .replace("{{task_count}}", &user.active_tasks.len().to_string())
+        .replace("{{max_tasks}}", &plan.max_tasks.to_string());
     
     tracing::info!("Successfully rendered dashboard for user: {}", username);
     Ok(Html(dashboard_html).into_response())
@@ -89,15 +141,13 @@ pub async fn serve_user_dashboard(
 
 pub async fn view_process(
     State((redis_service, _)): State<(RedisService, Config)>,
+    session: Session,
     Path(task_id): Path<String>,
 ) -> AppResult<Response> {
     tracing::info!("Viewing process for task: {}", task_id);
 
-    // Verify task exists before showing the processing page
-    let task = redis_service
-        .get_task(&task_id)
-        .await?
-        .ok_or_else(|| AppError::Task(format!("Task {} not found", task_id)))?;
+    // Verify the task exists and belongs to the user before showing the processing page
+    let task = get_user_task(&redis_service, &session, &task_id).await?;
 
     tracing::debug!("Found task with status: {:?}", task.status);
 
@@ -129,49 +179,136 @@ pub async fn delete_task(
 
     tracing::info!("Attempting to delete task {} for user {}", task_id, username);
 
-    // Get user data using RedisService
-    let mut user = redis_service
-        .get_user(&username)
-        .await?  // RedisError automatically converts to AppError::Redis
-        .ok_or_else(|| AppError::Auth("User not found".into()))?;
+    // Users can only delete their own tasks
+    let mut task = redis_service
+        .get_task(&task_id)
+        .await?
+        .filter(|task| task.user == username)
+        .ok_or_else(|| AppError::Task(format!("Task {} not found", task_id)))?;
 
-    // Remove task from user's task list
-    user.tasks.retain(|t| t != &task_id);
-    
-    // Update user data in Redis
-    redis_service
-        .save_user(&user)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to update user data: {}", e);
-            AppError::Redis(e)
-        })?;
-    
-    // Get task data to find result path
-    if let Some(task_info) = redis_service.get_task(&task_id).await? {
-        // Delete result files if they exist
-        let result_path = task_info.result_path;
-        if !result_path.is_empty() {
-            remove_dir_all(&result_path)
-                .await
-                .map_err(|e| {
-                    tracing::warn!("Failed to delete result directory {}: {}", result_path, e);
-                    AppError::File(e)
-                })?;
+    // Stop the task first so no worker keeps writing to the result directory
+    if matches!(task.status, TaskStatus::Queued | TaskStatus::Processing) {
+        request_cancellation(&redis_service, &mut task).await?;
+    }
+
+    // A worker still has the task: it settles the task's quota once it stops, then removes it
+    if matches!(task.status, TaskStatus::Queued | TaskStatus::Processing) {
+        redis_service
+            .mark_deleted(&task_id)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to mark task {} for deletion: {}", task_id, e);
+                AppError::Redis(e)
+            })?;
+
+        // The worker may have finished before it could see the mark
+        match redis_service.get_task(&task_id).await? {
+            Some(current) if !matches!(current.status, TaskStatus::Queued | TaskStatus::Processing) => task = current,
+            _ => {
+                tracing::info!("Task {} of user {} will be deleted once it stops", task_id, username);
+                return Ok(Redirect::to("/user").into_response());
+            }
         }
     }
-    
-    // Delete task data from Redis
-    redis_service
-        .delete_task(&task_id)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to delete task data from Redis: {}", e);
-            AppError::Redis(e)
-        })?;
+
+    remove_task(&redis_service, &task).await;
 
     tracing::info!("Successfully deleted task {} for user {}", task_id, username);
     
     // Redirect back to user dashboard
     Ok(Redirect::to("/user").into_response())
-} 
\ No newline at end of file
+}
+
+pub async fn cancel_task(
+    State((redis_service, _)): State<(RedisService, Config)>,
+    session: Session,
+    Path(task_id): Path<String>,
+) -> AppResult<Response> {
+    // Get username from session
+    let username = session
+        .get::<String>("user_session")
+        .await
+        .map_err(|e| AppError::Auth(format!("Session error: {}", e)))?
+        .ok_or_else(|| AppError::Auth("Not authenticated".into()))?;
+
+    tracing::info!("Attempting to cancel task {} for user {}", task_id, username);
+
+    // Users can only cancel their own tasks
+    let mut task = redis_service
+        .get_task(&task_id)
+        .await?
+        .filter(|task| task.user == username)
+        .ok_or_else(|| AppError::Task(format!("Task {} not found", task_id)))?;
+
+    if !matches!(task.status, TaskStatus::Queued | TaskStatus::Processing) {
+        return Err(AppError::Task(format!(
+            "Task {} is already {:?}",
+            task_id,
+            task.status
+        )));
+    }
+
+    request_cancellation(&redis_service, &mut task).await?;
+
+    // Redirect back to user dashboard
+    Ok(Redirect::to("/user").into_response())
+}
+
+// Helper function to cancel a task that has not finished yet
+// A queued task is taken out of the queue and cancelled right away, free of charge;
+// a running task gets a cancel flag and its worker marks it Cancelled once it stops
+async fn request_cancellation(redis_service: &RedisService, task: &mut TaskInfo) -> AppResult<()> {
+    if redis_service.remove_queued_task(task).await? {
+        task.status = TaskStatus::Cancelled;
+        task.completion_time = Some(Utc::now());
+        redis_service
+            .save_task(task)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to save cancelled task {}: {}", task.task_id, e);
+                AppError::Redis(e)
+            })?;
+        redis_service
+            .update_user(&task.user, |user| user.release_active_task(&task.task_id))
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to update user {}: {}", task.user, e);
+                AppError::Redis(e)
+            })?;
+        remove_input_file(&task.fasta_path).await;
+        tracing::info!("Removed queued task {} from the queue", task.task_id);
+    } else {
+        // Already taken by a worker
+        redis_service
+            .request_cancel(&task.task_id)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to request cancellation of task {}: {}", task.task_id, e);
+                AppError::Redis(e)
+            })?;
+        tracing::info!("Requested cancellation of running task {}", task.task_id);
+    }
+    Ok(())
+}
+
+// Helper function to delete a task's uploaded input, if it is still around
+async fn remove_input_file(path: &str) {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => tracing::info!("Deleted input file: {}", path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("Failed to delete input file {}: {}", path, e),
+    }
+}
+
+// Helper function to show a number of bytes in megabytes
+fn format_megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+// Helper function to make text safe to embed in HTML
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,7 +1,11 @@
 mod auth;
 mod task;
 mod dashboard;
+mod admin;
+mod batch;
 
 pub use auth::{serve_login_page, handle_login, handle_register, handle_logout};
-pub use task::{serve_upload_page, process_upload, get_task_status, download_results};
-pub use dashboard::{serve_user_dashboard, view_process, delete_task}; 
\ No newline at end of file
+pub use task::{serve_upload_page, process_upload, get_task_status, download_results, estimate_task};
+pub use dashboard::{serve_user_dashboard, view_process, delete_task, cancel_task};
+pub use batch::{view_batch, get_batch_status, download_batch};
+pub use admin::{list_dead_letter_tasks, requeue_dead_letter_task, assign_plan};
@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Multipart, State, Path, multipart::Field},
+    extract::{Multipart, State, Path, Query, multipart::Field},
     response::{Html, IntoResponse, Response, Json},
     http::{StatusCode, header},
     body::Body,
@@ -7,20 +7,22 @@ use axum::{
 use tower_sessions::Session;
 use std::{path::Path as FilePath, fs, io::Write};
 use chrono::Utc;
-use tokio::{
-    fs::File,
-    io::BufReader,
-};
-use tokio_util::io::ReaderStream;
 //use std::process::Command;
 use serde_json::json;
-use crate::models::{TaskInfo, TaskStatus, ProcessForm};
-use crate::services::RedisService;
-use crate::errors::{AppError, AppResult};
-use tracing;
-use crate::config::Config;
+use crate::models::{TaskInfo, TaskStatus, TaskPriority, ProcessForm, RunManifest, CacheLookup, CachedResult, User};
+use sha2::{Digest, Sha256};
+use crate::services::{RedisService, archive, estimator, result_cache, storage};
+use crate::services::estimator::Estimate;
+use crate::services::archive::{ArchiveEntry, ArchiveFormat};
+use crate::kmap_algorithms::alphabet::Alphabet;
+use serde::Deserialize;
+use super::batch::create_batch;
+use crate::errors::{AdmissionError, AppError, AppResult};
+use crate::config::{Config, GenomeConfig, PlanConfig};
 
-pub async fn serve_upload_page() -> AppResult<Response> {
+pub async fn serve_upload_page(
+    State((_, config)): State<(RedisService, Config)>,
+) -> AppResult<Response> {
     tracing::info!("Serving upload page");
     
     let upload_html = fs::read_to_string("templates/upload.html")
@@ -28,18 +30,41 @@ pub async fn serve_upload_page() -> AppResult<Response> {
             tracing::error!("Failed to read upload template: {}", e);
             AppError::File(e)
         })?;
+
+    // Offer the configured reference genomes for BED uploads
+    let genome_options = config.reference.genomes.iter()
+        .map(|genome| format!(r#"<option value="{0}">{0}</option>"#, genome.name))
+        .collect::<Vec<_>>()
+        .join("\n");
     
     tracing::debug!("Successfully loaded upload template");
-    Ok(Html(upload_html).into_response())
+    Ok(Html(upload_html.replace("{{genome_options}}", &genome_options)).into_response())
+}
+
+// One uploaded input file, saved in the user's temp directory
+pub(super) struct UploadedInput {
+    pub(super) path: String,
+    pub(super) filename: String,
+    sha256: String,
 }
 
 // Helper struct to hold form data during file upload processing
-struct UploadData {
-    fasta_path: Option<String>,
-    filename: Option<String>,
-    form: ProcessForm,
+pub(super) struct UploadData {
+    inputs: Vec<UploadedInput>,
+    input_is_bed: bool,
+    manifest: Option<RunManifest>,
+    pub(super) form: ProcessForm,
+    priority: TaskPriority,
 }
 
+// Estimates are rough, so only tasks expected to need well over the quota are turned away
+const QUOTA_REJECT_FACTOR: f64 = 2.0;
+
+// File names inside zip uploads that are taken as FASTA inputs
+const FASTA_EXTENSIONS: [&str; 5] = ["fa", "fasta", "fna", "fas", "txt"];
+// Most entries, files and directories alike, a zip upload may have
+const MAX_ZIP_ENTRIES: usize = 1000;
+
 pub async fn process_upload(
     State((redis_service, config)): State<(RedisService, Config)>,
     session: Session,
@@ -52,36 +77,180 @@ pub async fn process_upload(
         .map_err(|e| AppError::Auth(format!("Session error: {}", e)))?
         .ok_or_else(|| AppError::Auth("Not authenticated".into()))?;
 
+    // Refuse users at their task or storage limit before reading the upload
+    // create_and_queue_task checks again for every task
+    let user = redis_service
+        .get_user(&username)
+        .await?
+        .ok_or_else(|| AppError::Auth("User not found".into()))?;
+    let plan = config.plan(user.plan.as_deref());
+    check_admission(&user, &plan, false)
+        .inspect_err(|e| tracing::warn!("Refused upload of user {}: {}", username, e))?;
+
     // Process multipart form
-    let upload_data = process_multipart_form(&mut multipart, &username)
+    let mut upload_data = process_multipart_form(&mut multipart, &user, &config, &plan)
         .await
-        .map_err(|e| AppError::Upload(format!("Error processing upload: {}", e)))?;
+        .map_err(|e| match e {
+            AppError::Admission(_) => e,
+            e => AppError::Upload(format!("Error processing upload: {}", e)),
+        })?;
+    let mut inputs = std::mem::take(&mut upload_data.inputs);
+
+    // Several inputs run as a batch of tasks sharing the same parameters
+    if inputs.len() > 1 {
+        let batch_id = create_batch(&redis_service, &username, &config, &upload_data, inputs)
+            .await
+            .map_err(|e| match e {
+                AppError::Admission(_) => e,
+                e => AppError::Task(format!("Error creating batch: {}", e)),
+            })?;
+
+        let template = fs::read_to_string("templates/batch.html")
+            .map_err(AppError::File)?;
+        return Ok(Html(template.replace("{{batch_id}}", &batch_id)).into_response());
+    }
 
     // Create and queue task
-    let task_id = create_and_queue_task(&redis_service, &username, upload_data)
+    let input = inputs.pop()
+        .ok_or_else(|| AppError::Upload("No FASTA or BED file uploaded".into()))?;
+    let task_id = create_and_queue_task(&redis_service, &username, &config, &upload_data, input, None)
         .await
-        .map_err(|e| AppError::Task(format!("Error creating task: {}", e)))?;
+        .map_err(|e| match e {
+            AppError::Admission(_) => e,
+            e => AppError::Task(format!("Error creating task: {}", e)),
+        })?;
 
     // Read template file
     let template = fs::read_to_string("templates/processing.html")
-        .map_err(|e| AppError::File(e))?;
+        .map_err(AppError::File)?;
 
     // Return the response
     Ok(Html(template.replace("{{task_id}}", &task_id)).into_response())
 }
 
+// Parameters of a planned submission, sent by the upload page before submitting
+#[derive(Deserialize)]
+pub struct EstimateQuery {
+    input_bytes: String,  // Comma separated sizes of the files to upload, one task each
+    #[serde(default)]
+    bed: bool,
+    #[serde(default)]
+    n_trial: u32,
+    #[serde(default)]
+    top_k: u32,
+    #[serde(default)]
+    revcom_mode: bool,
+    #[serde(default)]
+    em_refine: bool,
+    #[serde(default)]
+    em_max_iter: u32,
+    #[serde(default)]
+    alphabet: Option<String>,
+    #[serde(default)]
+    window_width: u32,
+}
+
+// Estimate run time and peak memory of a submission, and whether it would be accepted
+pub async fn estimate_task(
+    State((redis_service, config)): State<(RedisService, Config)>,
+    session: Session,
+    Query(query): Query<EstimateQuery>,
+) -> AppResult<Response> {
+    let username = session
+        .get::<String>("user_session")
+        .await
+        .map_err(|e| AppError::Auth(format!("Session error: {}", e)))?
+        .ok_or_else(|| AppError::Auth("Not authenticated".into()))?;
+
+    let user = redis_service
+        .get_user(&username)
+        .await?
+        .ok_or_else(|| AppError::Auth("User not found".into()))?;
+
+    let alphabet: Alphabet = match query.alphabet.as_deref() {
+        Some(alphabet) if !alphabet.is_empty() => alphabet.parse()
+            .map_err(|e| AppError::Task(format!("Invalid alphabet '{}': {}", alphabet, e)))?,
+        _ => Alphabet::default(),
+    };
+    let form = ProcessForm {
+        n_trial: query.n_trial,
+        top_k: query.top_k,
+        revcom_mode: query.revcom_mode,
+        em_refine: query.em_refine,
+        em_max_iter: query.em_max_iter,
+        alphabet,
+        window_width: query.window_width,
+        ..ProcessForm::default()
+    };
+    let input_bytes = query.input_bytes
+        .split(',')
+        .filter(|size| !size.trim().is_empty())
+        .map(|size| size.trim().parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::Task(format!("Invalid input size: {}", e)))?;
+
+    let seconds_per_unit = estimator::calibrate(&redis_service.get_runtime_samples().await?);
+    let available_quota = user.available_quota(config.plan(user.plan.as_deref()).quota);
+    let estimates: Vec<Estimate> = input_bytes.iter()
+        .map(|&bytes| {
+            let sequence_bytes = estimator::sequence_bytes(bytes, query.bed, &form);
+            estimator::estimate(sequence_bytes, &form, seconds_per_unit)
+        })
+        .collect();
+    let rejection = estimates.iter()
+        .find_map(|estimate| estimate_rejection(estimate, available_quota, &config));
+
+    let response = json!({
+        "tasks": estimates,
+        "total_runtime_seconds": estimates.iter().map(|estimate| estimate.runtime_seconds).sum::<u64>(),
+        "available_quota": available_quota,
+        "max_task_duration": config.worker.max_task_duration,
+        "max_task_memory": config.worker.max_task_memory,
+        "rejection": rejection,
+    });
+    Ok(Json(response).into_response())
+}
+
+// Helper function to explain why a task with this estimate would be turned away, if it would be
+// A task may run for no longer than its user's remaining quota and max_task_duration
+fn estimate_rejection(estimate: &Estimate, available_quota: u64, config: &Config) -> Option<String> {
+    if estimate.peak_memory_bytes > config.worker.max_task_memory {
+        return Some(format!(
+            "The task would need about {} MB of memory, more than the {} MB a task may use",
+            estimate.peak_memory_bytes / (1024 * 1024),
+            config.worker.max_task_memory / (1024 * 1024)
+        ));
+    }
+
+    let allowed = available_quota.min(config.worker.max_task_duration);
+    if estimate.runtime_seconds as f64 > allowed as f64 * QUOTA_REJECT_FACTOR {
+        return Some(format!(
+            "The task would run for about {} seconds, but it can run for at most {} seconds of your quota",
+            estimate.runtime_seconds,
+            allowed
+        ));
+    }
+    None
+}
+
 // Helper function to process multipart form data from file upload
 // Extracts file information and form parameters
 async fn process_multipart_form(
     multipart: &mut Multipart,
-    username: &str,
+    user: &User,
+    config: &Config,
+    plan: &PlanConfig,
 ) -> AppResult<UploadData> {
+    let username = user.username.as_str();
     tracing::debug!("Processing multipart form for user: {}", username);
+    let temp_dir = &config.upload.temp_dir;
     
     let mut data = UploadData {
-        fasta_path: None,
-        filename: None,
+        inputs: Vec::new(),
+        input_is_bed: false,
+        manifest: None,
         form: ProcessForm::default(),
+        priority: TaskPriority::default(),
     };
 
     while let Some(field) = multipart.next_field().await.map_err(|e| {
@@ -89,11 +258,30 @@ async fn process_multipart_form(
         AppError::Upload(format!("Failed to process form field: {}", e))
     })? {
         match field.name().unwrap_or("") {
-            "fasta_file" => {
-                let (path, name) = handle_file_upload(field, username).await?;
-                data.fasta_path = Some(path);
-                tracing::debug!("Processed file upload: {}", &name);
-                data.filename = Some(name);
+            // Browsers send an empty part for a file input left blank
+            "fasta_file" | "bed_file" if field.file_name().is_none_or(str::is_empty) => {
+                tracing::debug!("Skipping empty file field: {}", field.name().unwrap_or(""));
+            }
+            "fasta_file" | "bed_file" => {
+                // Any number of FASTA files, or a single BED file
+                let is_bed = field.name() == Some("bed_file");
+                if !data.inputs.is_empty() && (is_bed || data.input_is_bed) {
+                    return Err(AppError::Upload("Upload either FASTA files or one BED file, not both".into()));
+                }
+                data.input_is_bed = is_bed;
+
+                let filename = unique_filename(&data.inputs, field.file_name().unwrap_or_default());
+                let is_zip = !is_bed && filename.to_ascii_lowercase().ends_with(".zip");
+                // A zip holds a whole batch, so only the request limit applies to it as a whole
+                let max_size = if is_zip { config.upload.max_request_size } else { plan.max_upload_size };
+                let (path, name, sha256) = handle_file_upload(field, username, temp_dir, filename, max_size as u64).await?;
+                tracing::debug!("Processed file upload: {} (BED: {})", &name, data.input_is_bed);
+
+                if is_zip {
+                    extract_zip_upload(&path, user, config, plan, &mut data.inputs)?;
+                } else {
+                    data.inputs.push(UploadedInput { path, filename: name, sha256 });
+                }
             }
             "n_trial" => {
                 data.form.n_trial = parse_field_value(field).await?;
@@ -111,6 +299,58 @@ async fn process_multipart_form(
                 data.form.min_ham_dist_mode = parse_bool_field(field).await?;
                 tracing::debug!("Processed min_ham_dist_mode: {}", data.form.min_ham_dist_mode);
             }
+            "em_refine" => {
+                data.form.em_refine = parse_bool_field(field).await?;
+                tracing::debug!("Processed em_refine: {}", data.form.em_refine);
+            }
+            "em_max_iter" => {
+                data.form.em_max_iter = parse_field_value(field).await?;
+                tracing::debug!("Processed em_max_iter: {}", data.form.em_max_iter);
+            }
+            "priority" => {
+                data.priority = parse_field_value(field).await?;
+                tracing::debug!("Processed priority: {:?}", data.priority);
+            }
+            "alphabet" => {
+                data.form.alphabet = parse_field_value(field).await?;
+                tracing::debug!("Processed alphabet: {:?}", data.form.alphabet);
+            }
+            "genome" => {
+                let genome: String = parse_field_value(field).await?;
+                data.form.genome = Some(genome).filter(|genome| !genome.is_empty());
+                tracing::debug!("Processed genome: {:?}", data.form.genome);
+            }
+            "recenter" => {
+                data.form.recenter = parse_bool_field(field).await?;
+                tracing::debug!("Processed recenter: {}", data.form.recenter);
+            }
+            "window_width" => {
+                data.form.window_width = parse_field_value(field).await?;
+                tracing::debug!("Processed window_width: {}", data.form.window_width);
+            }
+            "seed" => {
+                let seed: String = parse_field_value(field).await?;
+                data.form.seed = if seed.trim().is_empty() {
+                    None
+                } else {
+                    Some(seed.trim().parse().map_err(|e| AppError::Upload(format!(
+                        "Failed to parse seed '{}': {}",
+                        seed, e
+                    )))?)
+                };
+                tracing::debug!("Processed seed: {:?}", data.form.seed);
+            }
+            "manifest_file" if field.file_name().is_none_or(str::is_empty) => {
+                tracing::debug!("Skipping empty manifest field");
+            }
+            "manifest_file" => {
+                let bytes = field.bytes().await
+                    .map_err(|e| AppError::Upload(format!("Failed to read manifest: {}", e)))?;
+                let manifest: RunManifest = serde_json::from_slice(&bytes)
+                    .map_err(|e| AppError::Upload(format!("Invalid run manifest: {}", e)))?;
+                tracing::debug!("Processed run manifest with seed {}", manifest.seed);
+                data.manifest = Some(manifest);
+            }
             field_name => {
                 tracing::warn!("Unexpected form field: {}", field_name);
             }
@@ -118,9 +358,32 @@ async fn process_multipart_form(
     }
 
     // Validate required file was uploaded
-    if data.fasta_path.is_none() {
-        tracing::error!("No FASTA file was uploaded");
-        return Err(AppError::Upload("No FASTA file uploaded".into()));
+    if data.inputs.is_empty() {
+        tracing::error!("No FASTA or BED file was uploaded");
+        return Err(AppError::Upload("No FASTA or BED file uploaded".into()));
+    }
+
+    // A run manifest replaces all parameters, and must describe the uploaded input
+    if let Some(manifest) = &data.manifest {
+        if data.inputs.len() > 1 {
+            return Err(AppError::Upload("A run manifest describes a single input file".into()));
+        }
+        if data.inputs[0].sha256 != manifest.input_sha256 {
+            tracing::warn!("Uploaded input does not match manifest input {}", manifest.input_filename);
+            return Err(AppError::Upload(format!(
+                "Uploaded file does not match the manifest input {} (SHA-256 {})",
+                manifest.input_filename, manifest.input_sha256
+            )));
+        }
+        if manifest.version != env!("CARGO_PKG_VERSION") {
+            tracing::warn!(
+                "Manifest was written by version {}, running {}; output may differ",
+                manifest.version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+        data.form = manifest.params.clone();
+        data.form.seed = Some(manifest.seed);
     }
 
     tracing::debug!("Successfully processed multipart form for user: {}", username);
@@ -128,51 +391,201 @@ async fn process_multipart_form(
 }
 
 // Helper function to handle file upload process
-// Saves the uploaded file and returns its path and filename
+// Saves the uploaded file and returns its path, filename and SHA-256
 async fn handle_file_upload(
     mut field: Field<'_>,
     username: &str,
-) -> AppResult<(String, String)> {
-    // Get filename with better error handling
-    let filename = field
-        .file_name()
-        .ok_or_else(|| AppError::Upload("Missing filename in upload".into()))?
-        .to_string();
-
+    temp_dir: &str,
+    filename: String,
+    max_size: u64,
+) -> AppResult<(String, String, String)> {
     // Create temporary file
-    let temp_path = create_temp_file(username, &filename)
+    let temp_path = create_temp_file(username, temp_dir, &filename)
         .map_err(|e| AppError::Upload(format!("Failed to create temporary file: {}", e)))?;
 
     // Save the uploaded file
-    save_uploaded_file(&mut field, &temp_path)
+    let sha256 = save_uploaded_file(&mut field, &temp_path, max_size)
         .await
-        .map_err(|e| AppError::Upload(format!("Failed to save uploaded file: {}", e)))?;
+        .map_err(|e| match e {
+            AppError::Admission(_) => e,
+            e => AppError::Upload(format!("Failed to save uploaded file: {}", e)),
+        })?;
 
     tracing::debug!("Successfully handled file upload: {} -> {}", filename, temp_path);
-    Ok((temp_path, filename))
+    Ok((temp_path, filename, sha256))
+}
+
+// Helper function to unpack a zip upload into one input per FASTA file
+// The zip itself is deleted; files that are not FASTA are skipped
+// Nothing is written unless the user may submit every FASTA file of the zip
+fn extract_zip_upload(
+    zip_path: &str,
+    user: &User,
+    config: &Config,
+    plan: &PlanConfig,
+    inputs: &mut Vec<UploadedInput>,
+) -> AppResult<()> {
+    let archive = fs::read(zip_path).map_err(|e| {
+        tracing::error!("Failed to read zip upload {}: {}", zip_path, e);
+        AppError::File(e)
+    })?;
+    if let Err(e) = fs::remove_file(zip_path) {
+        tracing::warn!("Failed to delete zip upload {}: {}", zip_path, e);
+    }
+
+    // Every entry may be as large as a single uploaded file, and all of them together
+    // as large as an upload request
+    let entries = archive::read_zip(
+        &archive,
+        plan.max_upload_size as u64,
+        config.upload.max_request_size as u64,
+        MAX_ZIP_ENTRIES,
+    )
+    .map_err(|e| AppError::Upload(format!("Invalid zip file: {}", e)))?;
+
+    let entries: Vec<_> = entries
+        .into_iter()
+        .filter(|entry| {
+            let name = entry.name.rsplit('/').next().unwrap_or_default();
+            let is_fasta = FilePath::new(name)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| FASTA_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+            // Skip hidden files and the resource forks macOS adds to zips
+            let keep = is_fasta && !name.starts_with('.') && !entry.name.starts_with("__MACOSX/");
+            if !keep {
+                tracing::debug!("Skipping zip entry {}", entry.name);
+            }
+            keep
+        })
+        .collect();
+    check_batch_admission(user, plan, inputs.len() + entries.len())
+        .inspect_err(|e| tracing::warn!("Refused zip upload of user {}: {}", user.username, e))?;
+
+    let username = user.username.as_str();
+    let before = inputs.len();
+    for entry in entries {
+        let name = entry.name.rsplit('/').next().unwrap_or_default();
+        let filename = unique_filename(inputs, name);
+        let path = create_temp_file(username, &config.upload.temp_dir, &filename)?;
+        fs::write(&path, &entry.data).map_err(|e| {
+            tracing::error!("Failed to write {}: {}", path, e);
+            AppError::File(e)
+        })?;
+        let sha256 = format!("{:x}", Sha256::digest(&entry.data));
+        inputs.push(UploadedInput { path, filename, sha256 });
+    }
+
+    if inputs.len() == before {
+        return Err(AppError::Upload("The zip file contains no FASTA files".into()));
+    }
+    tracing::debug!("Extracted {} FASTA files from zip upload", inputs.len() - before);
+    Ok(())
+}
+
+// Helper function to give every input of an upload its own file name
+// Inputs share temp and result directories, so repeated names get a numeric suffix
+fn unique_filename(inputs: &[UploadedInput], filename: &str) -> String {
+    let taken = |name: &str| inputs.iter().any(|input| input.filename == name);
+    if !taken(filename) {
+        return filename.to_string();
+    }
+
+    let path = FilePath::new(filename);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(filename);
+    let extension = path.extension().and_then(|s| s.to_str());
+    (2..)
+        .map(|index| match extension {
+            Some(extension) => format!("{}_{}.{}", stem, index, extension),
+            None => format!("{}_{}", stem, index),
+        })
+        .find(|name| !taken(name))
+        .unwrap()
 }
 
 // Helper function to create and queue a new task
 // Creates task info and updates Redis with new task
-async fn create_and_queue_task(
+pub(super) async fn create_and_queue_task(
     redis_service: &RedisService,
     username: &str,
-    upload_data: UploadData,
+    config: &Config,
+    upload_data: &UploadData,
+    input: UploadedInput,
+    batch_id: Option<&str>,
 ) -> AppResult<String> {
     tracing::debug!("Creating and queueing task for user: {}", username);
     
     let task_id = uuid::Uuid::new_v4().to_string();
+
+    // Refuse what the user's plan does not allow before doing any work
+    // Task limit, storage and quota are checked again atomically when the task is added to the user
+    if let Err(e) = check_upload_admission(redis_service, username, config, &input.path).await {
+        tracing::warn!("Refused task {} for user {}: {}", task_id, username, e);
+        remove_uploaded_file(&input.path);
+        return Err(e);
+    }
+
+    let filename = input.filename;
+    let fasta_path = input.path;
     
-    // Clone the filename before unwrap and add error handling
-    let filename = upload_data.filename.clone()
-        .ok_or_else(|| AppError::Task("Missing filename in upload data".into()))?;
-    
+    // BED uploads need one of the configured reference genomes to extract sequences from
+    let genome = if upload_data.input_is_bed {
+        Some(reference_genome(config, &upload_data.form).inspect_err(|_| remove_uploaded_file(&fasta_path))?)
+    } else {
+        None
+    };
+    let reference_fasta = genome.map(|genome| genome.fasta_path.clone());
+
     // Create result directories - no need to map_err since it already returns AppResult
-    let result_path = create_result_directories(username, &filename)?;
+    let result_path = create_result_directories(username, &config.upload.results_dir, &filename, &task_id)
+        .inspect_err(|_| remove_uploaded_file(&fasta_path))?;
 
-    // Get fasta path with error handling
-    let fasta_path = upload_data.fasta_path
-        .ok_or_else(|| AppError::Task("Missing FASTA file path in upload data".into()))?;
+    // Every task records a seed; no stage draws random numbers yet, so it does not change the results
+    let mut params = upload_data.form.clone();
+    let seed = *params.seed.get_or_insert_with(rand::random);
+    tracing::debug!("Task {} uses seed {}", task_id, seed);
+
+    // An identical earlier submission completes this one at once, without using quota
+    let cache_key = result_cache::cache_key(&input.sha256, &params, genome);
+    let cached = link_cached_result(redis_service, &cache_key, &result_path)
+        .await
+        .inspect_err(|_| {
+            remove_uploaded_file(&fasta_path);
+            remove_result_directory(&result_path);
+        })?;
+    let cache = Some(CacheLookup {
+        key: cache_key,
+        hit: cached.is_some(),
+        source_task_id: cached.as_ref().map(|entry| entry.task_id.clone()),
+    });
+
+    let (status, result, completion_time, storage_bytes) = match cached {
+        Some(entry) => {
+            tracing::info!("Task {} reuses the results of task {}", task_id, entry.task_id);
+            // The linked manifest records the seed of the run that produced the results
+            params.seed = entry.seed;
+            remove_uploaded_file(&fasta_path);
+            // Linked results count against the storage quota like results of a run
+            let storage_bytes = storage::dir_size(FilePath::new(&result_path));
+            (TaskStatus::Completed, Some(entry.result), Some(Utc::now()), storage_bytes)
+        }
+        None => {
+            // Turn away tasks that would clearly run out of quota or memory
+            let rejection = check_task_estimate(redis_service, username, config, &fasta_path, reference_fasta.is_some(), &params)
+                .await
+                .inspect_err(|_| {
+                    remove_uploaded_file(&fasta_path);
+                    remove_result_directory(&result_path);
+                })?;
+            if let Some(rejection) = rejection {
+                tracing::warn!("Rejected task {} for user {}: {}", task_id, username, rejection);
+                remove_uploaded_file(&fasta_path);
+                remove_result_directory(&result_path);
+                return Err(AppError::Task(rejection));
+            }
+            (TaskStatus::Queued, None, None, 0)
+        }
+    };
 
     // Create task info
     let task_info = TaskInfo {
@@ -180,70 +593,228 @@ async fn create_and_queue_task(
         user: username.to_string(),
         fasta_path,
         filename,
-        status: TaskStatus::Queued,
-        params: upload_data.form,
-        result: None,
+        status,
+        params,
+        result,
         result_path,
         submission_time: Utc::now(),
-        completion_time: None,
+        completion_time,
+        reference_fasta,
+        input_sha256: Some(input.sha256),
+        attempts: 0,
+        start_time: None,
+        error: None,
+        reserved_quota: 0,
+        priority: upload_data.priority,
+        cache,
+        batch_id: batch_id.map(str::to_string),
+        storage_bytes,
     };
 
-    // Update user and queue task - no need to map_err since it already returns AppResult
-    update_user_and_queue_task(redis_service, username, &task_id, &task_info).await?;
+    // Update user and queue task, undoing the upload if the plan's limits were reached meanwhile
+    if let Err(e) = update_user_and_queue_task(redis_service, username, config, &task_id, &task_info).await {
+        if matches!(e, AppError::Admission(_)) {
+            tracing::warn!("Refused task {} for user {}: {}", task_id, username, e);
+            remove_uploaded_file(&task_info.fasta_path);
+            remove_result_directory(&task_info.result_path);
+        }
+        return Err(e);
+    }
 
     tracing::debug!("Successfully created and queued task: {}", task_id);
     Ok(task_id)
 }
 
+// Helper function to find the configured reference genome chosen for a BED upload
+fn reference_genome<'a>(config: &'a Config, form: &ProcessForm) -> AppResult<&'a GenomeConfig> {
+    let genome = form.genome.as_deref()
+        .ok_or_else(|| AppError::Task("A reference genome is required for BED uploads".into()))?;
+    config.reference.find_genome(genome)
+        .ok_or_else(|| AppError::Task(format!("Unknown reference genome: {}", genome)))
+}
+
+// Helper function to look a task up in the result cache
+// On a hit the cached results are linked into result_path and the entry is returned
+async fn link_cached_result(
+    redis_service: &RedisService,
+    cache_key: &str,
+    result_path: &str,
+) -> AppResult<Option<CachedResult>> {
+    let entry = match redis_service.get_cached_result(cache_key).await? {
+        Some(entry) => entry,
+        None => {
+            tracing::debug!("Result cache miss for key {}", cache_key);
+            return Ok(None);
+        }
+    };
+
+    match result_cache::link_results(FilePath::new(&entry.result_path), FilePath::new(result_path)) {
+        Ok(()) => Ok(Some(entry)),
+        Err(e) => {
+            // The cached results were deleted; forget them and run the task instead
+            tracing::warn!("Dropping cached results {} of task {}: {}", entry.result_path, entry.task_id, e);
+            redis_service.remove_cached_result(cache_key).await?;
+            fs::remove_dir_all(result_path)
+                .and_then(|_| fs::create_dir_all(result_path))
+                .map_err(|e| {
+                    tracing::error!("Failed to reset result directory {}: {}", result_path, e);
+                    AppError::File(e)
+                })?;
+            Ok(None)
+        }
+    }
+}
+
+// Helper function to estimate an uploaded task against the user's quota and the memory limit
+// Returns why the task should be rejected, or None to accept it
+async fn check_task_estimate(
+    redis_service: &RedisService,
+    username: &str,
+    config: &Config,
+    input_path: &str,
+    input_is_bed: bool,
+    params: &ProcessForm,
+) -> AppResult<Option<String>> {
+    let upload_bytes = fs::metadata(input_path)
+        .map_err(|e| {
+            tracing::error!("Failed to read size of {}: {}", input_path, e);
+            AppError::File(e)
+        })?
+        .len();
+    let user = redis_service
+        .get_user(username)
+        .await?
+        .ok_or_else(|| AppError::Task(format!("User {} not found", username)))?;
+    let available_quota = user.available_quota(config.plan(user.plan.as_deref()).quota);
+
+    let seconds_per_unit = estimator::calibrate(&redis_service.get_runtime_samples().await?);
+    let sequence_bytes = estimator::sequence_bytes(upload_bytes, input_is_bed, params);
+    let estimate = estimator::estimate(sequence_bytes, params, seconds_per_unit);
+    tracing::debug!(
+        "Estimated {} seconds and {} bytes of memory for {}",
+        estimate.runtime_seconds,
+        estimate.peak_memory_bytes,
+        input_path
+    );
+
+    Ok(estimate_rejection(&estimate, available_quota, config))
+}
+
+// Helper function to delete an upload that no worker will read
+pub(super) fn remove_uploaded_file(path: &str) {
+    if let Err(e) = fs::remove_file(path) {
+        tracing::warn!("Failed to delete uploaded file {}: {}", path, e);
+    }
+}
+
+// Helper function to delete the result directory of a task that was not created
+fn remove_result_directory(path: &str) {
+    if let Err(e) = fs::remove_dir_all(path) {
+        tracing::warn!("Failed to delete result directory {}: {}", path, e);
+    }
+}
+
+// Helper function to load a task of the logged in user
+pub(super) async fn get_user_task(
+    redis_service: &RedisService,
+    session: &Session,
+    task_id: &str,
+) -> AppResult<TaskInfo> {
+    let username = session
+        .get::<String>("user_session")
+        .await
+        .map_err(|e| AppError::Auth(format!("Session error: {}", e)))?
+        .ok_or_else(|| AppError::Auth("Not authenticated".into()))?;
+
+    // Users can only see their own tasks
+    redis_service
+        .get_task(task_id)
+        .await?
+        .filter(|task| task.user == username)
+        .ok_or_else(|| {
+            tracing::warn!("Task {} not found for user {}", task_id, username);
+            AppError::Task(format!("Task {} not found", task_id))
+        })
+}
+
 pub async fn get_task_status(
     Path(task_id): Path<String>,
-    State((redis_service, _)): State<(RedisService, Config)>,
+    State((redis_service, config)): State<(RedisService, Config)>,
+    session: Session,
 ) -> AppResult<Response> {
     tracing::debug!("Checking status for task: {}", task_id);
 
-    let task = redis_service
-        .get_task(&task_id)
-        .await?
-        .ok_or_else(|| {
-            tracing::warn!("Task not found: {}", task_id);
-            AppError::Task(format!("Task {} not found", task_id))
-        })?;
+    let task = get_user_task(&redis_service, &session, &task_id).await?;
 
     tracing::debug!("Task {} status: {:?}", task_id, task.status);
 
+    // Progress is reported only once a worker has started the task
+    let progress = redis_service.get_progress(&task_id).await?;
+
+    // Queued tasks get their place in line and a start estimate from recent run times
+    let (queue_position, estimated_start) = if matches!(task.status, TaskStatus::Queued) {
+        let queue_position = redis_service.queue_position(&task).await?;
+        let average_duration = redis_service.average_task_duration().await?;
+        let estimated_start = queue_position.zip(average_duration).map(|(position, average)| {
+            let wait = position as f64 * average / config.worker.max_concurrent_tasks.max(1) as f64;
+            Utc::now() + chrono::Duration::seconds(wait.round() as i64)
+        });
+        (queue_position, estimated_start)
+    } else {
+        (None, None)
+    };
+
     let response = json!({
         "task_id": task.task_id,
         "status": task.status,
         "result": task.result,
         "filename": task.filename,
         "submit_time": task.submission_time,
-        "complete_time": task.completion_time
+        "complete_time": task.completion_time,
+        "error": task.error,
+        "progress": progress,
+        "priority": task.priority,
+        "cache": task.cache,
+        "queue_position": queue_position,
+        "estimated_start": estimated_start
     });
 
     tracing::trace!("Sending task status response: {:?}", response);
     Ok(Json(response).into_response())
 }
 
+// Archive format of a download, zip unless ?format=tar.gz
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    #[serde(default)]
+    format: Option<String>,
+}
+
+impl DownloadQuery {
+    pub(super) fn archive_format(&self) -> AppResult<ArchiveFormat> {
+        match self.format.as_deref() {
+            Some(format) if !format.is_empty() => format.parse().map_err(AppError::Task),
+            _ => Ok(ArchiveFormat::Zip),
+        }
+    }
+}
+
 pub async fn download_results(
     Path(task_id): Path<String>,
     State((redis_service, _)): State<(RedisService, Config)>,
+    session: Session,
+    Query(query): Query<DownloadQuery>,
 ) -> AppResult<Response> {
     tracing::info!("Starting download for task_id: {}", task_id);
+    let format = query.archive_format()?;
     
-    // Get task info using RedisService
-    let task = redis_service
-        .get_task(&task_id)
-        .await?
-        .ok_or_else(|| {
-            tracing::warn!("Task not found: {}", task_id);
-            AppError::Task(format!("Task {} not found", task_id))
-        })?;
+    let task = get_user_task(&redis_service, &session, &task_id).await?;
     
     tracing::debug!("Found task, checking result path: {}", task.result_path);
-    let zip_path = format!("{}.zip", task.result_path);
+    let result_path = FilePath::new(&task.result_path);
     
     // Check if result directory exists
-    if !std::path::Path::new(&task.result_path).exists() {
+    if !result_path.exists() {
         tracing::error!("Result directory does not exist: {}", task.result_path);
         return Err(AppError::File(std::io::Error::new(
             std::io::ErrorKind::NotFound,
@@ -251,72 +822,63 @@ pub async fn download_results(
         )));
     }
 
-    // Create zip file and get its size
-    let file_size = create_zip_archive(&task.result_path, &zip_path).await?;
-    
-    tracing::debug!("Opening zip file for streaming");
-    let file = File::open(&zip_path).await
+    // Files go under a directory named like the result directory; its run manifest is
+    // written last with the checksums of every file added
+    let root = result_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("results");
+    let manifest_name = format!("{}/manifest.json", root);
+    let entries = archive::collect_files(result_path, root)
         .map_err(|e| {
-            tracing::error!("Failed to open zip file: {}", e);
+            tracing::error!("Failed to list results of task {}: {}", task_id, e);
             AppError::File(e)
-        })?;
-    
-    tracing::debug!("Preparing to send file: {} (size: {} bytes)", task.filename, file_size);
+        })?
+        .into_iter()
+        .filter(|entry| entry.name != manifest_name)
+        .collect();
+    let manifest = fs::read(result_path.join("manifest.json"))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_else(|| json!({}));
+
+    let filename = format!("results_{}.{}", task.filename, format.extension());
+    let response = archive_response(format, entries, manifest, manifest_name, &filename)?;
+    tracing::info!("Successfully prepared download response for task: {}", task_id);
+    Ok(response)
+}
 
-    // Create buffered reader and stream
-    let reader = BufReader::new(file);
-    // Convert to stream for chunk-by-chunk reading
-    let stream = ReaderStream::new(reader);
-    // Create HTTP response body from stream
-    let body = Body::from_stream(stream);
+// Helper function to send an archive as a download, streamed while it is written
+// The size is not known up front, so the response is chunked
+pub(super) fn archive_response(
+    format: ArchiveFormat,
+    entries: Vec<ArchiveEntry>,
+    manifest: serde_json::Value,
+    manifest_name: String,
+    filename: &str,
+) -> AppResult<Response> {
+    tracing::debug!("Streaming {} archive {} of {} files", format.extension(), filename, entries.len());
+    let body = Body::from_stream(archive::stream_archive(format, entries, manifest, manifest_name));
 
-    let filename = format!("results_{}.zip", task.filename);
-    
-    // Building the HTTP response
-    let response = Response::builder()
-        // Set HTTP status code to 200 OK
+    Response::builder()
         .status(StatusCode::OK)
-        // Tell browser this is a zip file
-        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_TYPE, format.content_type())
         // Tell browser to download file instead of displaying it
         .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
-        // Tell browser the total file size
-        .header(header::CONTENT_LENGTH, file_size.to_string())
-        // Attach the streaming body we created earlier
         .body(body)
-        // Handle any errors in building the response
         .map_err(|e| {
             tracing::error!("Failed to build response: {}", e);
             AppError::Task(format!("Failed to build download response: {}", e))
-        })?;
-
-    // Calculate a more appropriate timeout based on file size
-    // Assume a conservative download speed of 1MB/s
-    let timeout_secs = (file_size / (1024 * 1024) + 30) as u64;  // Add 30 seconds buffer
-    let zip_path_clone = zip_path.clone();
-    
-    tracing::debug!("Setting cleanup timeout to {} seconds", timeout_secs);
-    
-    tokio::spawn(async move {
-        tokio::time::sleep(tokio::time::Duration::from_secs(timeout_secs)).await;
-        if let Err(e) = tokio::fs::remove_file(&zip_path_clone).await {
-            tracing::error!("Failed to clean up zip file {}: {}", zip_path_clone, e);
-        } else {
-            tracing::info!("Successfully cleaned up zip file: {}", zip_path_clone);
-        }
-    });
-
-    tracing::info!("Successfully prepared download response for task: {}", task_id);
-    Ok(response)
+        })
 }
 
 // Helper function to create a temporary file path
 // Creates user-specific temp directory and generates unique filename
-fn create_temp_file(username: &str, filename: &str) -> AppResult<String> {
+fn create_temp_file(username: &str, temp_dir: &str, filename: &str) -> AppResult<String> {
     tracing::debug!("Creating temporary file for user: {}", username);
     
     // Create user-specific temp directory only if it doesn't exist
-    let user_temp_dir = format!("temp/{}", username);
+    let user_temp_dir = format!("{}/{}", temp_dir, username);
     if !std::path::Path::new(&user_temp_dir).exists() {
         std::fs::create_dir_all(&user_temp_dir).map_err(|e| {
             tracing::error!("Failed to create temp directory {}: {}", user_temp_dir, e);
@@ -334,11 +896,13 @@ fn create_temp_file(username: &str, filename: &str) -> AppResult<String> {
 }
 
 // Helper function to save uploaded file chunks
-// Writes file data to disk using buffered writer
+// Writes file data to disk using buffered writer and returns its SHA-256 hex digest
+// A file growing beyond max_size bytes is deleted and refused
 async fn save_uploaded_file(
     field: &mut Field<'_>,
     temp_path: &str,
-) -> AppResult<()> {
+    max_size: u64,
+) -> AppResult<String> {
     tracing::debug!("Starting to save uploaded file to: {}", temp_path);
     
     // Create file with buffered writer
@@ -347,9 +911,20 @@ async fn save_uploaded_file(
         AppError::File(e)
     })?;
     let mut writer = std::io::BufWriter::new(file);
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
     
     // Read and write chunks
     while let Ok(Some(chunk)) = field.chunk().await {
+        size += chunk.len() as u64;
+        if size > max_size {
+            drop(writer);
+            remove_uploaded_file(temp_path);
+            let filename = field.file_name().unwrap_or_default().to_string();
+            tracing::warn!("Refused upload of {}: larger than {} bytes", filename, max_size);
+            return Err(AdmissionError::FileTooLarge { filename, limit: max_size }.into());
+        }
+        hasher.update(&chunk);
         writer.write_all(&chunk).map_err(|e| {
             tracing::error!("Error writing chunk to {}: {}", temp_path, e);
             AppError::File(e)
@@ -363,16 +938,16 @@ async fn save_uploaded_file(
     })?;
     
     tracing::debug!("Successfully saved uploaded file to: {}", temp_path);
-    Ok(())
+    Ok(format!("{:x}", hasher.finalize()))
 }
 
 // Helper function to create result directories
-// Creates user-specific result directory with timestamp
-fn create_result_directories(username: &str, filename: &str) -> AppResult<String> {
+// Creates user-specific result directory with timestamp and task id
+fn create_result_directories(username: &str, results_dir: &str, filename: &str, task_id: &str) -> AppResult<String> {
     tracing::debug!("Creating result directories for user: {}", username);
     
     // Create base results directory for user only if it doesn't exist
-    let user_result_dir = format!("results/{}", username);
+    let user_result_dir = format!("{}/{}", results_dir, username);
     if !std::path::Path::new(&user_result_dir).exists() {
         std::fs::create_dir_all(&user_result_dir).map_err(|e| {
             tracing::error!("Failed to create user result directory {}: {}", user_result_dir, e);
@@ -386,9 +961,10 @@ fn create_result_directories(username: &str, filename: &str) -> AppResult<String
         .and_then(|s| s.to_str())
         .unwrap_or("unknown");
     
-    // Create unique directory for this task using timestamp
+    // Create unique directory for this task using timestamp and task id
+    // Inputs of one batch may share a stem and are created within the same second
     let timestamp = chrono::Utc::now().timestamp();
-    let result_path = format!("{}/{}_{}", user_result_dir, base_name, timestamp);
+    let result_path = format!("{}/{}_{}_{}", user_result_dir, base_name, timestamp, task_id);
     
     std::fs::create_dir_all(&result_path).map_err(|e| {
         tracing::error!("Failed to create task result directory {}: {}", result_path, e);
@@ -404,24 +980,31 @@ fn create_result_directories(username: &str, filename: &str) -> AppResult<String
 async fn update_user_and_queue_task(
     redis_service: &RedisService,
     username: &str,
+    config: &Config,
     task_id: &str,
     task_info: &TaskInfo,
 ) -> AppResult<()> {
     tracing::debug!("Updating user data and queueing task for user: {}", username);
     
-    // Get current user data
-    let mut user = redis_service.get_user(username).await?
-        .ok_or_else(|| AppError::Task(format!("User {} not found", username)))?;
-    
-    // Add task to user's task list
-    user.tasks.push(task_id.to_string());
-    
-    // Save updated user data
-    redis_service.save_user(&user).await
+    // Add task to user's task list, with the storage of results reused from the cache
+    // The plan's limits are checked in the same transaction, so concurrent uploads cannot exceed them
+    let queued = matches!(task_info.status, TaskStatus::Queued);
+    redis_service
+        .update_user(username, |user| {
+            check_admission(user, &config.plan(user.plan.as_deref()), queued)?;
+            user.tasks.push(task_id.to_string());
+            if queued {
+                user.active_tasks.push(task_id.to_string());
+            }
+            user.used_storage += task_info.storage_bytes;
+            Ok::<_, AdmissionError>(())
+        })
+        .await
         .map_err(|e| {
             tracing::error!("Failed to update user data for {}: {}", username, e);
             AppError::Redis(e)
-        })?;
+        })?
+        .ok_or_else(|| AppError::Task(format!("User {} not found", username)))??;
     
     // Save task info
     redis_service.save_task(task_info).await
@@ -430,17 +1013,78 @@ async fn update_user_and_queue_task(
             AppError::Redis(e)
         })?;
     
-    // Queue task for processing
-    redis_service.queue_task(task_info).await
-        .map_err(|e| {
-            tracing::error!("Failed to queue task {}: {}", task_id, e);
-            AppError::Redis(e)
-        })?;
+    // Queue task for processing, unless it was completed from the result cache
+    if matches!(task_info.status, TaskStatus::Queued) {
+        redis_service.queue_task(task_info).await
+            .map_err(|e| {
+                tracing::error!("Failed to queue task {}: {}", task_id, e);
+                AppError::Redis(e)
+            })?;
+    }
     
     tracing::debug!("Successfully updated user data and queued task: {}", task_id);
     Ok(())
 }
 
+// Helper function to check a user against the limits of their plan before adding a task
+// The task limit counts the user's queued and running tasks; queued tasks also need quota,
+// while tasks completed from the result cache do not
+fn check_admission(user: &User, plan: &PlanConfig, queued: bool) -> Result<(), AdmissionError> {
+    if user.active_tasks.len() >= plan.max_tasks {
+        return Err(AdmissionError::TaskLimit(plan.max_tasks));
+    }
+    if user.used_storage >= plan.storage_quota {
+        return Err(AdmissionError::StorageQuotaExceeded {
+            used: user.used_storage,
+            limit: plan.storage_quota,
+        });
+    }
+    if queued && user.available_quota(plan.quota) == 0 {
+        return Err(AdmissionError::QuotaExhausted);
+    }
+    Ok(())
+}
+
+// Helper function to check that a user may submit count more tasks at once
+pub(super) fn check_batch_admission(user: &User, plan: &PlanConfig, count: usize) -> Result<(), AdmissionError> {
+    if user.active_tasks.len() + count > plan.max_tasks {
+        return Err(AdmissionError::TaskLimit(plan.max_tasks));
+    }
+    check_admission(user, plan, false)
+}
+
+// Helper function to check an uploaded input against the user's plan
+// Whether the task will need quota is only known after the cache lookup, so quota is left
+// to the check when the task is added
+async fn check_upload_admission(
+    redis_service: &RedisService,
+    username: &str,
+    config: &Config,
+    input_path: &str,
+) -> AppResult<()> {
+    let user = redis_service
+        .get_user(username)
+        .await?
+        .ok_or_else(|| AppError::Task(format!("User {} not found", username)))?;
+    let plan = config.plan(user.plan.as_deref());
+
+    let size = fs::metadata(input_path)
+        .map_err(|e| {
+            tracing::error!("Failed to read size of {}: {}", input_path, e);
+            AppError::File(e)
+        })?
+        .len();
+    if size > plan.max_upload_size as u64 {
+        return Err(AdmissionError::UploadTooLarge {
+            size,
+            limit: plan.max_upload_size as u64,
+        }.into());
+    }
+
+    check_admission(&user, &plan, false)?;
+    Ok(())
+}
+
 // Helper function to parse form field values
 // Generic function to parse form fields into specified types
 async fn parse_field_value<T>(
@@ -478,50 +1122,65 @@ async fn parse_bool_field(
     }
 }
 
-// Helper function for creating zip archives
-// Creates a zip file from source directory and returns its size
-async fn create_zip_archive(source_path: &str, zip_path: &str) -> AppResult<u64> {
-    tracing::debug!("Creating zip archive from {} to {}", source_path, zip_path);
-    
-    // Ensure source directory exists before attempting to zip
-    if !std::path::Path::new(source_path).exists() {
-        return Err(AppError::File(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("Source directory not found: {}", source_path)
-        )));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan() -> PlanConfig {
+        PlanConfig {
+            name: "free".into(),
+            quota: 3600,
+            max_tasks: 2,
+            max_upload_size: 1024,
+            storage_quota: 1000,
+            reset_period: None,
+        }
     }
 
-    // Use tokio::process::Command for async execution
-    let output = tokio::process::Command::new("zip")
-        .arg("-rq")  // recursive and quiet mode
-        .arg(zip_path)
-        .arg(source_path)  
-        .output()
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to execute zip command: {}", e);
-            AppError::Task(format!("Failed to create zip file: {}", e))
-        })?;
+    fn user(tasks: usize, active_tasks: usize) -> User {
+        User {
+            username: "alice".into(),
+            password_hash: String::new(),
+            tasks: (0..tasks).map(|i| format!("task-{}", i)).collect(),
+            active_tasks: (0..active_tasks).map(|i| format!("task-{}", i)).collect(),
+            used_quota: 0,
+            reserved_quota: 0,
+            used_storage: 0,
+            plan: Some("free".into()),
+            quota_reset_at: None,
+        }
+    }
 
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        tracing::error!("Zip command failed: {}", error);
-        return Err(AppError::Task(format!("Zip creation failed: {}", error)));
+    #[test]
+    fn task_limit_counts_only_active_tasks() {
+        assert!(check_admission(&user(1, 1), &plan(), true).is_ok());
+        assert!(matches!(check_admission(&user(2, 2), &plan(), true), Err(AdmissionError::TaskLimit(2))));
+        // Finished tasks the user keeps do not count
+        assert!(check_admission(&user(50, 1), &plan(), true).is_ok());
     }
 
-    // Verify zip file was created and get its size
-    let metadata = tokio::fs::metadata(zip_path).await.map_err(|e| {
-        tracing::error!("Failed to verify zip file: {}", e);
-        AppError::File(e)
-    })?;
+    #[test]
+    fn storage_and_quota_are_checked() {
+        let mut full = user(1, 0);
+        full.used_storage = 1000;
+        assert!(matches!(
+            check_admission(&full, &plan(), false),
+            Err(AdmissionError::StorageQuotaExceeded { used: 1000, limit: 1000 })
+        ));
 
-    let file_size = metadata.len();
-    if file_size == 0 {
-        tracing::error!("Created zip file is empty: {}", zip_path);
-        return Err(AppError::Task("Created zip file is empty".into()));
+        // Quota still reserved by running tasks is not available
+        let mut spent = user(1, 1);
+        spent.used_quota = 3000;
+        spent.reserved_quota = 600;
+        assert!(matches!(check_admission(&spent, &plan(), true), Err(AdmissionError::QuotaExhausted)));
+        // Tasks completed from the result cache need no quota
+        assert!(check_admission(&spent, &plan(), false).is_ok());
     }
 
-    tracing::debug!("Successfully created zip archive: {} (size: {} bytes)", zip_path, file_size);
-    Ok(file_size)
-} 
-
+    #[test]
+    fn batches_must_fit_the_task_limit_as_a_whole() {
+        assert!(check_batch_admission(&user(0, 0), &plan(), 2).is_ok());
+        assert!(matches!(check_batch_admission(&user(0, 1), &plan(), 2), Err(AdmissionError::TaskLimit(2))));
+        assert!(check_batch_admission(&user(10, 0), &plan(), 2).is_ok());
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+const DNA_LETTERS: &[u8] = b"ACGT";
+const RNA_LETTERS: &[u8] = b"ACGU";
+const PROTEIN_LETTERS: &[u8] = b"ACDEFGHIKLMNPQRSTVWY";
+
+// Sequence alphabet of an analysis
+// K-mers are packed into a u64 with bits_per_symbol bits per letter, first letter highest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Alphabet {
+    #[default]
+    Dna,
+    Rna,
+    Protein,
+}
+
+impl Alphabet {
+    pub fn letters(&self) -> &'static [u8] {
+        match self {
+            Alphabet::Dna => DNA_LETTERS,
+            Alphabet::Rna => RNA_LETTERS,
+            Alphabet::Protein => PROTEIN_LETTERS,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.letters().len()
+    }
+
+    pub fn bits_per_symbol(&self) -> u32 {
+        match self {
+            Alphabet::Dna | Alphabet::Rna => 2,
+            Alphabet::Protein => 5,
+        }
+    }
+
+    // Longest k-mer that still fits in a u64
+    pub fn max_kmer_length(&self) -> usize {
+        (u64::BITS / self.bits_per_symbol()) as usize
+    }
+
+    // Only double-stranded DNA has a reverse complement
+    pub fn supports_revcom(&self) -> bool {
+        matches!(self, Alphabet::Dna)
+    }
+
+    // Code of a letter, None for anything outside the alphabet
+    // RNA accepts T as U so that DNA-encoded transcripts can be analysed directly
+    pub fn encode(&self, symbol: u8) -> Option<u64> {
+        let symbol = symbol.to_ascii_uppercase();
+        let symbol = match (self, symbol) {
+            (Alphabet::Rna, b'T') => b'U',
+            _ => symbol,
+        };
+        self.letters().iter().position(|&letter| letter == symbol).map(|code| code as u64)
+    }
+
+    // Letter for a code produced by encode
+    pub fn decode(&self, code: u64) -> u8 {
+        self.letters()[code as usize]
+    }
+
+    // MEME ALPHABET line value
+    pub fn meme_name(&self) -> &'static str {
+        match self {
+            Alphabet::Dna => "ACGT",
+            Alphabet::Rna => "ACGU",
+            Alphabet::Protein => "ACDEFGHIKLMNPQRSTVWY",
+        }
+    }
+}
+
+impl std::str::FromStr for Alphabet {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "dna" => Ok(Alphabet::Dna),
+            "rna" => Ok(Alphabet::Rna),
+            "protein" => Ok(Alphabet::Protein),
+            _ => Err(format!("unknown alphabet '{}', expected dna, rna or protein", value)),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use bio::alphabets::dna;
+use bio::io::fasta;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+// A genomic interval in BED coordinates (0-based, end exclusive)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenomicInterval {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub name: Option<String>,
+    pub reverse: bool,
+    // Summit offset from start, as in column 10 of narrowPeak files
+    pub summit: Option<u64>,
+}
+
+impl GenomicInterval {
+    // FASTA identifier carrying the coordinates, e.g. chr1:100-200(+)
+    pub fn fasta_id(&self) -> String {
+        format!(
+            "{}:{}-{}({})",
+            self.chrom,
+            self.start,
+            self.end,
+            if self.reverse { '-' } else { '+' }
+        )
+    }
+
+    // Genomic span of a site found at an offset of the extracted sequence
+    // Sequences from minus-strand intervals were reverse complemented, so offsets run from end
+    pub fn site_coordinates(&self, offset: u64, width: u64) -> (u64, u64) {
+        if self.reverse {
+            (self.end - offset - width, self.end - offset)
+        } else {
+            (self.start + offset, self.start + offset + width)
+        }
+    }
+}
+
+fn invalid_data(line_number: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("BED line {}: {}", line_number, message),
+    )
+}
+
+// Parse a BED file, skipping blank, comment, track and browser lines
+// Columns beyond the first three are optional (name, score, strand, ..., narrowPeak summit)
+pub fn parse_bed(path: &Path) -> io::Result<Vec<GenomicInterval>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut intervals = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = index + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty()
+            || trimmed.starts_with('#')
+            || trimmed.starts_with("track")
+            || trimmed.starts_with("browser")
+        {
+            continue;
+        }
+
+        let fields: Vec<&str> = trimmed.split('\t').collect();
+        if fields.len() < 3 {
+            return Err(invalid_data(line_number, "expected at least 3 tab-separated columns"));
+        }
+        let start: u64 = fields[1]
+            .parse()
+            .map_err(|_| invalid_data(line_number, "invalid start"))?;
+        let end: u64 = fields[2]
+            .parse()
+            .map_err(|_| invalid_data(line_number, "invalid end"))?;
+        if end <= start {
+            return Err(invalid_data(line_number, "end must be greater than start"));
+        }
+
+        intervals.push(GenomicInterval {
+            chrom: fields[0].to_string(),
+            start,
+            end,
+            name: fields.get(3).filter(|name| !name.is_empty() && **name != ".").map(|name| name.to_string()),
+            reverse: fields.get(5) == Some(&"-"),
+            summit: fields.get(9).and_then(|summit| summit.parse::<i64>().ok())
+                .and_then(|summit| u64::try_from(summit).ok())
+                .filter(|&summit| start + summit < end),
+        });
+    }
+
+    Ok(intervals)
+}
+
+// Window options applied before extraction
+#[derive(Debug, Clone, Copy)]
+pub struct WindowOptions {
+    // Centre windows on the narrowPeak summit instead of the interval midpoint
+    pub recenter: bool,
+    // Fixed window width around the centre, 0 keeps the width of each interval
+    pub window_width: u64,
+}
+
+// Resize an interval to the requested window, clamped to the chromosome
+// Without a fixed width, recentred windows keep the width of their interval
+fn apply_window(interval: &GenomicInterval, options: WindowOptions, chrom_len: u64) -> GenomicInterval {
+    let mut window = interval.clone();
+    window.end = window.end.min(chrom_len);
+    let recentre = options.recenter && interval.summit.is_some();
+    if options.window_width == 0 && !recentre {
+        return window;
+    }
+
+    let centre = match (options.recenter, interval.summit) {
+        (true, Some(summit)) => interval.start + summit,
+        _ => interval.start + (interval.end - interval.start) / 2,
+    };
+    let width = match options.window_width {
+        0 => interval.end - interval.start,
+        width => width,
+    };
+    let half = width / 2;
+    window.start = centre.saturating_sub(half);
+    window.end = (window.start + width).min(chrom_len);
+    window.summit = None;
+    window
+}
+
+// Extract the sequence of every interval from an indexed reference FASTA (.fa + .fai)
+// Minus-strand intervals are reverse complemented, intervals on unknown or too short
+// chromosomes are skipped. The sequences are written to output_fasta with their
+// coordinates as identifiers, and the windows actually extracted are returned in order
+pub fn extract_sequences(
+    intervals: &[GenomicInterval],
+    reference_fasta: &Path,
+    options: WindowOptions,
+    output_fasta: &Path,
+) -> anyhow::Result<Vec<GenomicInterval>> {
+    let mut reader = fasta::IndexedReader::from_file(&reference_fasta)?;
+    let chrom_lengths: HashMap<String, u64> = reader
+        .index
+        .sequences()
+        .into_iter()
+        .map(|sequence| (sequence.name, sequence.len))
+        .collect();
+
+    let mut writer = fasta::Writer::to_file(output_fasta)?;
+    let mut extracted = Vec::with_capacity(intervals.len());
+    let mut seq = Vec::new();
+
+    for interval in intervals {
+        let Some(&chrom_len) = chrom_lengths.get(&interval.chrom) else {
+            tracing::warn!("Skipping interval on unknown chromosome {}", interval.chrom);
+            continue;
+        };
+        let window = apply_window(interval, options, chrom_len);
+        if window.start >= window.end {
+            tracing::warn!("Skipping interval outside chromosome: {}", interval.fasta_id());
+            continue;
+        }
+
+        reader.fetch(&window.chrom, window.start, window.end)?;
+        reader.read(&mut seq)?;
+        if window.reverse {
+            seq = dna::revcomp(&seq);
+        }
+
+        writer.write(&window.fasta_id(), window.name.as_deref(), &seq)?;
+        extracted.push(window);
+    }
+
+    writer.flush()?;
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(start: u64, end: u64, reverse: bool, summit: Option<u64>) -> GenomicInterval {
+        GenomicInterval { chrom: "chr1".into(), start, end, name: None, reverse, summit }
+    }
+
+    fn span(window: &GenomicInterval) -> (u64, u64) {
+        (window.start, window.end)
+    }
+
+    #[test]
+    fn apply_window_keeps_intervals_without_width() {
+        let options = WindowOptions { recenter: false, window_width: 0 };
+        assert_eq!(span(&apply_window(&interval(100, 200, false, Some(10)), options, 1000)), (100, 200));
+        // Clamped to the chromosome
+        assert_eq!(span(&apply_window(&interval(900, 1100, false, None), options, 1000)), (900, 1000));
+    }
+
+    #[test]
+    fn apply_window_centres_fixed_width() {
+        let options = WindowOptions { recenter: false, window_width: 50 };
+        let window = apply_window(&interval(100, 200, false, Some(10)), options, 1000);
+        assert_eq!(span(&window), (125, 175));
+        assert_eq!(window.summit, None);
+
+        // Near the chromosome edges the window is cut short
+        assert_eq!(span(&apply_window(&interval(0, 20, false, None), options, 1000)), (0, 50));
+        assert_eq!(span(&apply_window(&interval(980, 1000, false, None), options, 1000)), (965, 1000));
+    }
+
+    #[test]
+    fn apply_window_recentres_on_summit() {
+        let fixed = WindowOptions { recenter: true, window_width: 50 };
+        assert_eq!(span(&apply_window(&interval(100, 200, false, Some(10)), fixed, 1000)), (85, 135));
+
+        // Without a fixed width the interval keeps its width around the summit
+        let keep_width = WindowOptions { recenter: true, window_width: 0 };
+        assert_eq!(span(&apply_window(&interval(100, 200, false, Some(10)), keep_width, 1000)), (60, 160));
+        // Intervals without a summit stay as they are
+        assert_eq!(span(&apply_window(&interval(100, 200, false, None), keep_width, 1000)), (100, 200));
+    }
+
+    #[test]
+    fn site_coordinates_follow_the_strand() {
+        let plus = interval(1000, 1100, false, None);
+        assert_eq!(plus.site_coordinates(0, 8), (1000, 1008));
+        assert_eq!(plus.site_coordinates(10, 8), (1010, 1018));
+
+        // Minus-strand sequences were reverse complemented, so offset 0 is the interval end
+        let minus = interval(1000, 1100, true, None);
+        assert_eq!(minus.site_coordinates(0, 8), (1092, 1100));
+        assert_eq!(minus.site_coordinates(10, 8), (1082, 1090));
+        assert_eq!(minus.fasta_id(), "chr1:1000-1100(-)");
+    }
+
+    #[test]
+    fn parse_bed_reads_optional_columns() {
+        let path = std::env::temp_dir().join(format!("kmap-bed-test-{}.bed", std::process::id()));
+        std::fs::write(
+            &path,
+            "track name=peaks\n# comment\n\nchr1\t100\t200\tpeak1\t0\t-\t0\t0\t0\t40\nchr2\t5\t15\n",
+        )
+        .unwrap();
+        let intervals = parse_bed(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(intervals.len(), 2);
+        assert_eq!(intervals[0].name.as_deref(), Some("peak1"));
+        assert!(intervals[0].reverse);
+        assert_eq!(intervals[0].summit, Some(40));
+        assert_eq!(span(&intervals[1]), (5, 15));
+        assert!(!intervals[1].reverse);
+        assert_eq!(intervals[1].summit, None);
+    }
+}
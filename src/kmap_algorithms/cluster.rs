@@ -0,0 +1,339 @@
+use serde::{Deserialize, Serialize};
+use super::pwm::Pwm;
+
+// Motifs whose average-linkage distance stays below this form one family
+pub const DEFAULT_MAX_CLUSTER_DISTANCE: f64 = 0.3;
+
+// Shortest overlap considered when sliding one PWM along another
+const MIN_OVERLAP: usize = 4;
+
+// Pearson correlation of two PWM columns, 0 when either column is uniform
+fn column_correlation(a: &[f64], b: &[f64]) -> f64 {
+    let mean_a = a.iter().sum::<f64>() / a.len() as f64;
+    let mean_b = b.iter().sum::<f64>() / b.len() as f64;
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a <= f64::EPSILON || var_b <= f64::EPSILON {
+        return 0.0;
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+// Best summed column correlation over every offset of b against a
+fn best_offset_score(a: &Pwm, b: &Pwm) -> f64 {
+    let (wa, wb) = (a.width() as isize, b.width() as isize);
+    let min_overlap = MIN_OVERLAP.min(a.width()).min(b.width()) as isize;
+    let mut best = f64::NEG_INFINITY;
+
+    // offset is the position in a where b's first column lands
+    for offset in (min_overlap - wb)..=(wa - min_overlap) {
+        let start = offset.max(0);
+        let end = (offset + wb).min(wa);
+        let score: f64 = (start..end)
+            .map(|i| column_correlation(&a.rows[i as usize], &b.rows[(i - offset) as usize]))
+            .sum();
+        best = best.max(score);
+    }
+    best
+}
+
+// Shift- and strand-aware similarity in [-1, 1]
+// Summed column correlations at the best alignment, normalized by the wider motif
+// so that a short partial overlap cannot look like a perfect match
+// The opposite strand is only considered for DNA
+pub fn pwm_similarity(a: &Pwm, b: &Pwm, revcom_mode: bool) -> f64 {
+    if a.width() == 0 || b.width() == 0 {
+        return 0.0;
+    }
+    let mut best = best_offset_score(a, b);
+    if revcom_mode && b.alphabet.supports_revcom() {
+        best = best.max(best_offset_score(a, &b.revcom()));
+    }
+    best / a.width().max(b.width()) as f64
+}
+
+// One node of an average-linkage dendrogram
+// Leaves come first (index = motif index), internal nodes follow in merge order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DendrogramNode {
+    pub children: Option<(usize, usize)>,
+    pub distance: f64,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dendrogram {
+    pub nodes: Vec<DendrogramNode>,
+}
+
+impl Dendrogram {
+    // Build the tree bottom-up with UPGMA from a symmetric distance matrix
+    pub fn upgma(distances: &[Vec<f64>]) -> Self {
+        let n = distances.len();
+        let mut nodes: Vec<DendrogramNode> = (0..n)
+            .map(|_| DendrogramNode { children: None, distance: 0.0, size: 1 })
+            .collect();
+
+        // Distances between active clusters, indexed by node id
+        let mut dist: Vec<Vec<f64>> = distances.to_vec();
+        let mut active: Vec<usize> = (0..n).collect();
+
+        while active.len() > 1 {
+            let mut best = (0, 1, f64::INFINITY);
+            for (i, &a) in active.iter().enumerate() {
+                for &b in &active[i + 1..] {
+                    if dist[a][b] < best.2 {
+                        best = (a, b, dist[a][b]);
+                    }
+                }
+            }
+            let (a, b, merge_distance) = best;
+
+            let new_id = nodes.len();
+            let (size_a, size_b) = (nodes[a].size, nodes[b].size);
+            nodes.push(DendrogramNode {
+                children: Some((a, b)),
+                distance: merge_distance,
+                size: size_a + size_b,
+            });
+
+            // Average linkage: size-weighted mean of the merged clusters' distances
+            let mut row = vec![0.0; new_id + 1];
+            for &c in &active {
+                if c != a && c != b {
+                    let d = (dist[a][c] * size_a as f64 + dist[b][c] * size_b as f64)
+                        / (size_a + size_b) as f64;
+                    row[c] = d;
+                }
+            }
+            for (c, existing) in dist.iter_mut().enumerate() {
+                existing.push(row[c]);
+            }
+            dist.push(row);
+
+            active.retain(|&c| c != a && c != b);
+            active.push(new_id);
+        }
+
+        Self { nodes }
+    }
+
+    pub fn root(&self) -> Option<usize> {
+        self.nodes.len().checked_sub(1)
+    }
+
+    // Leaves in left-to-right drawing order
+    pub fn leaf_order(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut stack: Vec<usize> = self.root().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            match self.nodes[node].children {
+                Some((left, right)) => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+                None => order.push(node),
+            }
+        }
+        order
+    }
+
+    // Leaves under a node
+    pub fn leaves(&self, node: usize) -> Vec<usize> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            match self.nodes[node].children {
+                Some((left, right)) => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+                None => leaves.push(node),
+            }
+        }
+        leaves
+    }
+
+    // Cut the tree so that every cluster merges below max_distance
+    pub fn cut(&self, max_distance: f64) -> Vec<Vec<usize>> {
+        let mut clusters = Vec::new();
+        let mut stack: Vec<usize> = self.root().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            match self.nodes[node].children {
+                Some((left, right)) if self.nodes[node].distance > max_distance => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+                _ => clusters.push(self.leaves(node)),
+            }
+        }
+        clusters
+    }
+
+    // Newick string with branch lengths as half the merge-distance difference (UPGMA heights)
+    pub fn to_newick(&self, labels: &[String]) -> String {
+        fn write_node(tree: &Dendrogram, labels: &[String], node: usize, out: &mut String) {
+            if let Some((left, right)) = tree.nodes[node].children {
+                out.push('(');
+                for (i, child) in [left, right].into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_node(tree, labels, child, out);
+                    let branch = (tree.nodes[node].distance - tree.nodes[child].distance) / 2.0;
+                    out.push_str(&format!(":{:.6}", branch.max(0.0)));
+                }
+                out.push(')');
+            } else {
+                let label: String = labels[node]
+                    .chars()
+                    .map(|c| if "(),:;[] \t".contains(c) { '_' } else { c })
+                    .collect();
+                out.push_str(&label);
+            }
+        }
+
+        let mut out = String::new();
+        if let Some(root) = self.root() {
+            write_node(self, labels, root, &mut out);
+        }
+        out.push(';');
+        out
+    }
+}
+
+// A family of similar motifs and the member most similar to the rest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MotifCluster {
+    pub cluster_id: usize,
+    pub members: Vec<usize>,
+    pub representative: usize,
+}
+
+// Result of clustering motifs: the full dendrogram and the families cut from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MotifClustering {
+    pub dendrogram: Dendrogram,
+    pub newick: String,
+    pub clusters: Vec<MotifCluster>,
+}
+
+// Cluster motifs with average linkage over 1 - similarity and cut at max_distance
+// The representative of each cluster is its medoid (highest mean similarity to the others)
+pub fn cluster_motifs(
+    pwms: &[&Pwm],
+    labels: &[String],
+    revcom_mode: bool,
+    max_distance: f64,
+) -> MotifClustering {
+    let n = pwms.len();
+    let mut similarity = vec![vec![1.0; n]; n];
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let s = pwm_similarity(pwms[i], pwms[j], revcom_mode);
+            similarity[i][j] = s;
+            similarity[j][i] = s;
+        }
+    }
+    let distances: Vec<Vec<f64>> = similarity
+        .iter()
+        .map(|row| row.iter().map(|s| 1.0 - s).collect())
+        .collect();
+
+    let dendrogram = Dendrogram::upgma(&distances);
+    let newick = dendrogram.to_newick(labels);
+
+    let mut clusters: Vec<MotifCluster> = dendrogram
+        .cut(max_distance)
+        .into_iter()
+        .map(|mut members| {
+            members.sort_unstable();
+            let representative = members
+                .iter()
+                .copied()
+                .max_by(|&a, &b| {
+                    let mean = |i: usize| members.iter().map(|&j| similarity[i][j]).sum::<f64>();
+                    mean(a).total_cmp(&mean(b)).then(b.cmp(&a))
+                })
+                .unwrap_or(0);
+            MotifCluster { cluster_id: 0, members, representative }
+        })
+        .collect();
+
+    // Number clusters by their best-ranked member so cluster 0 holds the top motif
+    clusters.sort_by_key(|cluster| cluster.members[0]);
+    for (id, cluster) in clusters.iter_mut().enumerate() {
+        cluster.cluster_id = id;
+    }
+
+    MotifClustering { dendrogram, newick, clusters }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two tight pairs, {0, 1} and {2, 3}, far apart
+    fn distances() -> Vec<Vec<f64>> {
+        vec![
+            vec![0.0, 0.1, 0.8, 0.9],
+            vec![0.1, 0.0, 0.7, 0.8],
+            vec![0.8, 0.7, 0.0, 0.2],
+            vec![0.9, 0.8, 0.2, 0.0],
+        ]
+    }
+
+    #[test]
+    fn upgma_merges_closest_clusters_with_average_linkage() {
+        let tree = Dendrogram::upgma(&distances());
+
+        assert_eq!(tree.nodes.len(), 7);
+        assert_eq!(tree.nodes[4].children, Some((0, 1)));
+        assert_eq!(tree.nodes[4].distance, 0.1);
+        assert_eq!(tree.nodes[5].children, Some((2, 3)));
+        assert_eq!(tree.nodes[5].distance, 0.2);
+        assert_eq!(tree.nodes[6].children, Some((4, 5)));
+        assert_eq!(tree.nodes[6].size, 4);
+        // Mean of the four distances between the pairs
+        assert!((tree.nodes[6].distance - 0.8).abs() < 1e-12);
+        assert_eq!(tree.root(), Some(6));
+        assert_eq!(tree.leaf_order(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn cut_splits_above_max_distance() {
+        let tree = Dendrogram::upgma(&distances());
+
+        assert_eq!(tree.cut(1.0), vec![vec![0, 1, 2, 3]]);
+        assert_eq!(tree.cut(0.5), vec![vec![0, 1], vec![2, 3]]);
+        assert_eq!(tree.cut(0.15), vec![vec![0, 1], vec![2], vec![3]]);
+        assert_eq!(tree.cut(0.0), vec![vec![0], vec![1], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn to_newick_writes_upgma_branch_lengths_and_escapes_labels() {
+        let tree = Dendrogram::upgma(&distances());
+        let labels: Vec<String> = ["m1", "m2", "m:3", "m 4"].iter().map(|label| label.to_string()).collect();
+
+        assert_eq!(
+            tree.to_newick(&labels),
+            "((m1:0.050000,m2:0.050000):0.350000,(m_3:0.100000,m_4:0.100000):0.300000);"
+        );
+    }
+
+    #[test]
+    fn single_and_empty_trees() {
+        let single = Dendrogram::upgma(&[vec![0.0]]);
+        assert_eq!(single.to_newick(&["only".to_string()]), "only;");
+        assert_eq!(single.cut(0.5), vec![vec![0]]);
+
+        let empty = Dendrogram::upgma(&[]);
+        assert_eq!(empty.root(), None);
+        assert_eq!(empty.to_newick(&[]), ";");
+        assert!(empty.cut(0.5).is_empty());
+    }
+}
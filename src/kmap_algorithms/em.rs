@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use super::alphabet::Alphabet;
+use super::pwm::Pwm;
+
+const BACKGROUND_PSEUDOCOUNT: f64 = 1.0;
+const MSTEP_PSEUDOCOUNT: f64 = 0.5;
+const INITIAL_SITE_PRIOR: f64 = 0.5;
+const CONVERGENCE_TOL: f64 = 1e-6;
+const INVALID_SYMBOL: u8 = u8::MAX;
+
+// Outcome of refining one seed PWM with EM
+// Log-likelihoods are relative to the background-only model of the same sequences
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmResult {
+    pub pwm: Pwm,
+    pub iterations: u32,
+    pub initial_log_likelihood: f64,
+    pub log_likelihood: f64,
+    pub log_likelihood_improvement: f64,
+    pub site_prior: f64,
+    pub expected_sites: f64,
+    pub sites: usize,
+}
+
+// Sequences encoded once, shared by every seed refined against them
+pub struct EmInput {
+    alphabet: Alphabet,
+    sequences: Vec<Vec<u8>>,
+    background: Vec<f64>,
+}
+
+impl EmInput {
+    pub fn new(sequences: &[Vec<u8>], alphabet: Alphabet) -> Self {
+        let sequences: Vec<Vec<u8>> = sequences
+            .iter()
+            .map(|seq| {
+                seq.iter()
+                    .map(|&symbol| alphabet.encode(symbol).map_or(INVALID_SYMBOL, |code| code as u8))
+                    .collect()
+            })
+            .collect();
+
+        let mut counts = vec![BACKGROUND_PSEUDOCOUNT; alphabet.size()];
+        for &code in sequences.iter().flatten() {
+            if code != INVALID_SYMBOL {
+                counts[code as usize] += 1.0;
+            }
+        }
+        let total: f64 = counts.iter().sum();
+        let background = counts.iter().map(|count| count / total).collect();
+
+        Self { alphabet, sequences, background }
+    }
+
+    pub fn background(&self) -> &[f64] {
+        &self.background
+    }
+}
+
+// Expected sufficient statistics from one E-step
+struct EStep {
+    log_likelihood: f64,
+    counts: Vec<Vec<f64>>,
+    expected_sites: f64,
+    sites: usize,
+}
+
+// Letter code read at a motif position, complemented on the reverse strand
+// Complementing a 2-bit DNA code is 3 - code
+fn site_code(window: &[u8], pos: usize, reverse: bool) -> u8 {
+    if reverse {
+        let code = window[window.len() - 1 - pos];
+        if code == INVALID_SYMBOL { code } else { 3 - code }
+    } else {
+        window[pos]
+    }
+}
+
+// Likelihood ratio of a site against background, 0 if the window holds an invalid letter
+fn site_ratio(ratios: &[Vec<f64>], window: &[u8], reverse: bool) -> f64 {
+    let mut ratio = 1.0;
+    for (pos, row) in ratios.iter().enumerate() {
+        let code = site_code(window, pos, reverse);
+        if code == INVALID_SYMBOL {
+            return 0.0;
+        }
+        ratio *= row[code as usize];
+    }
+    ratio
+}
+
+// Add a site's posterior weight to the per-position letter counts
+fn add_site(counts: &mut [Vec<f64>], window: &[u8], reverse: bool, weight: f64) {
+    for (pos, row) in counts.iter_mut().enumerate() {
+        row[site_code(window, pos, reverse) as usize] += weight;
+    }
+}
+
+fn e_step(input: &EmInput, pwm: &Pwm, site_prior: f64, revcom_mode: bool) -> EStep {
+    let width = pwm.width();
+    let ratios: Vec<Vec<f64>> = pwm
+        .rows
+        .iter()
+        .map(|row| row.iter().zip(&input.background).map(|(p, bg)| p / bg).collect())
+        .collect();
+    let strands: &[bool] = if revcom_mode { &[false, true] } else { &[false] };
+
+    let mut step = EStep {
+        log_likelihood: 0.0,
+        counts: vec![vec![0.0; input.alphabet.size()]; width],
+        expected_sites: 0.0,
+        sites: 0,
+    };
+    let mut site_ratios = Vec::new();
+
+    for seq in &input.sequences {
+        if seq.len() < width {
+            continue;
+        }
+
+        // Likelihood ratio of every site position on every searched strand
+        site_ratios.clear();
+        for window in seq.windows(width) {
+            for &reverse in strands {
+                site_ratios.push(site_ratio(&ratios, window, reverse));
+            }
+        }
+
+        // Zero-or-one occurrence: the prior mass is spread evenly over all positions
+        let position_prior = site_prior / site_ratios.len() as f64;
+        let denom = 1.0 - site_prior + position_prior * site_ratios.iter().sum::<f64>();
+        step.log_likelihood += denom.ln();
+
+        let mut seq_posterior = 0.0;
+        let windows = seq.windows(width).flat_map(|w| strands.iter().map(move |&r| (w, r)));
+        for ((window, reverse), ratio) in windows.zip(&site_ratios) {
+            let posterior = position_prior * ratio / denom;
+            if posterior > 0.0 {
+                add_site(&mut step.counts, window, reverse, posterior);
+                seq_posterior += posterior;
+            }
+        }
+
+        step.expected_sites += seq_posterior;
+        if seq_posterior > 0.5 {
+            step.sites += 1;
+        }
+    }
+
+    step
+}
+
+// Refine a seed PWM with expectation-maximization under the ZOOPS
+// (zero-or-one-occurrence-per-sequence) model, stopping after max_iter
+// iterations or once the log-likelihood stops improving
+// Both strands are searched only for DNA in revcom mode
+pub fn refine_zoops(input: &EmInput, seed: &Pwm, max_iter: u32, revcom_mode: bool) -> EmResult {
+    let revcom_mode = revcom_mode && input.alphabet.supports_revcom();
+    let n_sequences = input.sequences.len().max(1) as f64;
+    let mut pwm = seed.clone();
+    let mut site_prior = INITIAL_SITE_PRIOR;
+    let mut iterations = 0;
+
+    let mut step = e_step(input, &pwm, site_prior, revcom_mode);
+    let initial_log_likelihood = step.log_likelihood;
+
+    while iterations < max_iter {
+        // M-step: re-estimate the PWM and the site prior from expected counts
+        let counts: Vec<Vec<f64>> = step
+            .counts
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(&input.background)
+                    .map(|(count, bg)| count + MSTEP_PSEUDOCOUNT * bg)
+                    .collect()
+            })
+            .collect();
+        pwm = Pwm::from_counts(input.alphabet, &counts, 0.0);
+        site_prior = (step.expected_sites / n_sequences).clamp(1e-6, 1.0 - 1e-6);
+        iterations += 1;
+
+        let previous_log_likelihood = step.log_likelihood;
+        step = e_step(input, &pwm, site_prior, revcom_mode);
+        if step.log_likelihood - previous_log_likelihood
+            <= CONVERGENCE_TOL * previous_log_likelihood.abs().max(1.0)
+        {
+            break;
+        }
+    }
+
+    EmResult {
+        pwm,
+        iterations,
+        initial_log_likelihood,
+        log_likelihood: step.log_likelihood,
+        log_likelihood_improvement: step.log_likelihood - initial_log_likelihood,
+        site_prior,
+        expected_sites: step.expected_sites,
+        sites: step.sites,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOTIF: &[u8] = b"CCGATAA";
+
+    // Deterministic background sequences with the motif planted once in each
+    fn sequences_with_motif(count: usize, len: usize, reverse: bool) -> Vec<Vec<u8>> {
+        let motif: Vec<u8> = if reverse { bio::alphabets::dna::revcomp(MOTIF) } else { MOTIF.to_vec() };
+        let mut state: u64 = 42;
+        (0..count)
+            .map(|i| {
+                let mut seq: Vec<u8> = (0..len)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        b"ACGT"[(state >> 62) as usize]
+                    })
+                    .collect();
+                let at = (i * 7) % (len - motif.len());
+                seq[at..at + motif.len()].copy_from_slice(&motif);
+                seq
+            })
+            .collect()
+    }
+
+    // Seed that favours the motif's letters only weakly
+    fn weak_seed() -> Pwm {
+        let counts: Vec<Vec<f64>> = MOTIF
+            .iter()
+            .map(|&letter| {
+                let code = Alphabet::Dna.encode(letter).unwrap() as usize;
+                (0..4).map(|i| if i == code { 2.0 } else { 1.0 }).collect()
+            })
+            .collect();
+        Pwm::from_counts(Alphabet::Dna, &counts, 0.0)
+    }
+
+    #[test]
+    fn refine_zoops_sharpens_planted_motif() {
+        let input = EmInput::new(&sequences_with_motif(40, 60, false), Alphabet::Dna);
+        let result = refine_zoops(&input, &weak_seed(), 50, false);
+
+        assert_eq!(result.pwm.consensus(), "CCGATAA");
+        assert!(result.iterations > 0);
+        assert!(result.log_likelihood_improvement > 0.0);
+        assert!(result.sites >= 38, "found {} sites", result.sites);
+        assert!(result.site_prior > 0.9);
+        for row in &result.pwm.rows {
+            assert!((row.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn refine_zoops_finds_reverse_strand_sites_in_revcom_mode() {
+        let input = EmInput::new(&sequences_with_motif(40, 60, true), Alphabet::Dna);
+        let single = refine_zoops(&input, &weak_seed(), 50, false);
+        let both = refine_zoops(&input, &weak_seed(), 50, true);
+
+        assert!(both.sites >= 38, "found {} sites", both.sites);
+        assert!(both.log_likelihood > single.log_likelihood);
+    }
+
+    #[test]
+    fn refine_zoops_without_iterations_keeps_seed() {
+        let input = EmInput::new(&sequences_with_motif(5, 30, false), Alphabet::Dna);
+        let seed = weak_seed();
+        let result = refine_zoops(&input, &seed, 0, false);
+
+        assert_eq!(result.iterations, 0);
+        assert_eq!(result.pwm.rows, seed.rows);
+        assert_eq!(result.log_likelihood, result.initial_log_likelihood);
+    }
+
+    #[test]
+    fn background_skips_invalid_letters() {
+        let input = EmInput::new(&[b"AAAN".to_vec()], Alphabet::Dna);
+        // Three A plus one pseudocount per letter
+        assert_eq!(input.background(), &[4.0 / 7.0, 1.0 / 7.0, 1.0 / 7.0, 1.0 / 7.0]);
+    }
+}
@@ -0,0 +1,44 @@
+use super::alphabet::Alphabet;
+use super::kmer_count::{hamming_distance, revcom_hash, symbol_at};
+use super::pwm::Pwm;
+
+const SEED_PSEUDOCOUNT: f64 = 0.5;
+
+// Build a seed PWM from every counted k-mer within max_ham_dist of the seed
+// Each k-mer in the ball adds its count to the letters it carries at each position
+// In revcom mode the counts are keyed by canonical k-mer, so each key is aligned
+// to the seed in whichever orientation lies closer
+pub fn hamming_ball_pwm(
+    seed: u64,
+    kmer_length: usize,
+    max_ham_dist: u32,
+    kmer_counts: &[(u64, u32)],
+    alphabet: Alphabet,
+    revcom_mode: bool,
+) -> Pwm {
+    let revcom_mode = revcom_mode && alphabet.supports_revcom();
+    let mut counts = vec![vec![0.0f64; alphabet.size()]; kmer_length];
+
+    for &(kmer, count) in kmer_counts {
+        let mut aligned = kmer;
+        let mut distance = hamming_distance(kmer, seed, alphabet);
+        if revcom_mode {
+            let revcom = revcom_hash(kmer, kmer_length);
+            let revcom_distance = hamming_distance(revcom, seed, alphabet);
+            if revcom_distance < distance {
+                aligned = revcom;
+                distance = revcom_distance;
+            }
+        }
+        if distance > max_ham_dist {
+            continue;
+        }
+
+        for (pos, row) in counts.iter_mut().enumerate() {
+            let code = symbol_at(aligned, pos, kmer_length, alphabet);
+            row[code as usize] += count as f64;
+        }
+    }
+
+    Pwm::from_counts(alphabet, &counts, SEED_PSEUDOCOUNT)
+}
@@ -1 +1,190 @@
-// kmer_count.rs will be updated in the future.
+use bio::io::fasta;
+use std::collections::HashMap;
+use super::alphabet::Alphabet;
+
+// k-mer length the pipeline counts and builds seed motifs from
+pub const KMER_LENGTH: usize = 8;
+
+// Load all records from a FASTA file as (identifier, upper-case sequence) pairs
+// Unreadable records are logged and skipped
+pub fn load_fasta(path: &str) -> Vec<(String, Vec<u8>)> {
+    let reader = match fasta::Reader::from_file(path) {
+        Ok(reader) => reader,
+        Err(e) => {
+            tracing::error!("Failed to open FASTA file {}: {}", path, e);
+            return Vec::new();
+        }
+    };
+
+    reader
+        .records()
+        .filter_map(|record| match record {
+            Ok(record) => Some((record.id().to_string(), record.seq().to_ascii_uppercase())),
+            Err(e) => {
+                tracing::warn!("Skipping malformed FASTA record in {}: {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+// Bit mask covering one packed k-mer
+fn kmer_mask(kmer_length: usize, alphabet: Alphabet) -> u64 {
+    let bits = kmer_length as u32 * alphabet.bits_per_symbol();
+    if bits >= u64::BITS { u64::MAX } else { (1u64 << bits) - 1 }
+}
+
+// Code of the symbol at a position of a packed k-mer
+pub fn symbol_at(hash: u64, pos: usize, kmer_length: usize, alphabet: Alphabet) -> u64 {
+    let bits = alphabet.bits_per_symbol();
+    (hash >> (bits * (kmer_length - 1 - pos) as u32)) & ((1 << bits) - 1)
+}
+
+// Unpack a k-mer back into its letters
+pub fn hash2kmer(hash: u64, kmer_length: usize, alphabet: Alphabet) -> Vec<u8> {
+    (0..kmer_length)
+        .map(|pos| alphabet.decode(symbol_at(hash, pos, kmer_length, alphabet)))
+        .collect()
+}
+
+// Reverse complement of a 2-bit encoded DNA k-mer
+pub fn revcom_hash(hash: u64, kmer_length: usize) -> u64 {
+    let mut hash = hash;
+    let mut revcom = 0u64;
+    for _ in 0..kmer_length {
+        revcom = (revcom << 2) | (3 - (hash & 3));
+        hash >>= 2;
+    }
+    revcom
+}
+
+// Number of mismatching positions between two packed k-mers
+pub fn hamming_distance(a: u64, b: u64, alphabet: Alphabet) -> u32 {
+    let bits = alphabet.bits_per_symbol();
+    let diff = a ^ b;
+
+    // Fold every symbol's bits onto its lowest bit, then count symbols that differ
+    let folded = (0..bits).fold(0u64, |folded, shift| folded | (diff >> shift));
+    let low_bits = (0..u64::BITS / bits).fold(0u64, |mask, i| mask | (1 << (i * bits)));
+    (folded & low_bits).count_ones()
+}
+
+// Count every k-mer over all sequences
+// In revcom mode a DNA k-mer and its reverse complement share the canonical (smaller) hash
+pub fn count_kmers_in_sequences(
+    sequences: &[Vec<u8>],
+    kmer_length: usize,
+    alphabet: Alphabet,
+    revcom_mode: bool,
+) -> HashMap<u64, u32> {
+    let mut counts = HashMap::new();
+    if kmer_length == 0 || kmer_length > alphabet.max_kmer_length() {
+        return counts;
+    }
+    let mask = kmer_mask(kmer_length, alphabet);
+    let bits = alphabet.bits_per_symbol();
+    let revcom_mode = revcom_mode && alphabet.supports_revcom();
+
+    for seq in sequences {
+        let mut hash = 0u64;
+        let mut valid = 0usize;
+        for &symbol in seq {
+            match alphabet.encode(symbol) {
+                Some(code) => {
+                    hash = ((hash << bits) | code) & mask;
+                    valid += 1;
+                }
+                None => valid = 0,
+            }
+            if valid >= kmer_length {
+                let key = if revcom_mode {
+                    hash.min(revcom_hash(hash, kmer_length))
+                } else {
+                    hash
+                };
+                *counts.entry(key).or_insert(0) += 1;
+            }
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to pack a k-mer the way count_kmers_in_sequences does
+    fn pack(kmer: &[u8], alphabet: Alphabet) -> u64 {
+        kmer.iter().fold(0, |hash, &symbol| (hash << alphabet.bits_per_symbol()) | alphabet.encode(symbol).unwrap())
+    }
+
+    #[test]
+    fn packing_round_trips_for_every_alphabet() {
+        for (alphabet, kmer) in [
+            (Alphabet::Dna, &b"ACGTTGCA"[..]),
+            (Alphabet::Rna, &b"ACGUUGCA"[..]),
+            (Alphabet::Protein, &b"MKWVTFIS"[..]),
+        ] {
+            let hash = pack(kmer, alphabet);
+            assert_eq!(hash2kmer(hash, kmer.len(), alphabet), kmer);
+            assert_eq!(alphabet.decode(symbol_at(hash, 0, kmer.len(), alphabet)), kmer[0]);
+        }
+        // First letter in the highest bits
+        assert_eq!(pack(b"CA", Alphabet::Dna), 0b0100);
+    }
+
+    #[test]
+    fn longest_kmers_fill_a_u64() {
+        assert_eq!(Alphabet::Dna.max_kmer_length(), 32);
+        assert_eq!(Alphabet::Protein.max_kmer_length(), 12);
+
+        let kmer = b"TTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTT";
+        let counts = count_kmers_in_sequences(&[kmer.to_vec()], 32, Alphabet::Dna, false);
+        assert_eq!(counts.get(&u64::MAX), Some(&1));
+        assert!(count_kmers_in_sequences(&[kmer.to_vec()], 33, Alphabet::Dna, false).is_empty());
+    }
+
+    #[test]
+    fn encode_is_case_insensitive_and_reads_t_as_u_for_rna() {
+        assert_eq!(Alphabet::Dna.encode(b'g'), Some(2));
+        assert_eq!(Alphabet::Rna.encode(b'T'), Alphabet::Rna.encode(b'U'));
+        assert_eq!(Alphabet::Dna.encode(b'N'), None);
+        assert_eq!(Alphabet::Protein.encode(b'B'), None);
+    }
+
+    #[test]
+    fn hamming_distance_counts_differing_symbols() {
+        let dna = |kmer: &[u8]| pack(kmer, Alphabet::Dna);
+        assert_eq!(hamming_distance(dna(b"ACGTACGT"), dna(b"ACGTACGT"), Alphabet::Dna), 0);
+        // A -> T flips both bits of a symbol but is one mismatch
+        assert_eq!(hamming_distance(dna(b"ACGTACGT"), dna(b"TCGTACGA"), Alphabet::Dna), 2);
+        assert_eq!(hamming_distance(dna(b"AAAAAAAA"), dna(b"CGTCGTCG"), Alphabet::Dna), 8);
+
+        let protein = |kmer: &[u8]| pack(kmer, Alphabet::Protein);
+        assert_eq!(hamming_distance(protein(b"MKWVTFIS"), protein(b"MKWVTFIA"), Alphabet::Protein), 1);
+        assert_eq!(hamming_distance(protein(b"MKWVTFIS"), protein(b"YKWVTFIS"), Alphabet::Protein), 1);
+    }
+
+    #[test]
+    fn revcom_mode_counts_both_strands_under_the_canonical_kmer() {
+        let sequences = vec![b"AACG".to_vec(), b"CGTT".to_vec()];
+        let forward = count_kmers_in_sequences(&sequences, 4, Alphabet::Dna, false);
+        assert_eq!(forward.len(), 2);
+
+        let both = count_kmers_in_sequences(&sequences, 4, Alphabet::Dna, true);
+        assert_eq!(both.len(), 1);
+        assert_eq!(both.get(&pack(b"AACG", Alphabet::Dna)), Some(&2));
+        assert_eq!(revcom_hash(pack(b"AACG", Alphabet::Dna), 4), pack(b"CGTT", Alphabet::Dna));
+
+        // Only DNA has a reverse complement
+        let rna = count_kmers_in_sequences(&[b"AACG".to_vec(), b"CGUU".to_vec()], 4, Alphabet::Rna, true);
+        assert_eq!(rna.len(), 2);
+    }
+
+    #[test]
+    fn invalid_letters_restart_the_kmer() {
+        let counts = count_kmers_in_sequences(&[b"ACGNACG".to_vec()], 3, Alphabet::Dna, false);
+        assert_eq!(counts.get(&pack(b"ACG", Alphabet::Dna)), Some(&2));
+        assert_eq!(counts.values().sum::<u32>(), 2);
+    }
+}
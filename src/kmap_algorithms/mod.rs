@@ -1 +1,11 @@
-pub mod kmer_count; 
\ No newline at end of file
+pub mod alphabet;
+pub mod kmer_count;
+pub mod pwm;
+pub mod motif_table;
+pub mod hamming_ball;
+pub mod em;
+pub mod motif;
+pub mod cluster;
+pub mod motif_plot;
+pub mod bed;
+pub mod site_scan;
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use super::alphabet::Alphabet;
+use super::cluster::MotifClustering;
+use super::em::EmResult;
+use super::pwm::Pwm;
+
+// One discovered motif: its seed k-mer, the Hamming-ball PWM built around it
+// and, when requested, the EM-refined PWM
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Motif {
+    pub seed: String,
+    pub seed_count: u32,
+    pub consensus: String,
+    pub seed_pwm: Pwm,
+    pub refinement: Option<EmResult>,
+}
+
+impl Motif {
+    // The refined PWM if refinement ran, otherwise the seed PWM
+    pub fn final_pwm(&self) -> &Pwm {
+        self.refinement.as_ref().map_or(&self.seed_pwm, |em| &em.pwm)
+    }
+
+    // Label used in the dendrogram, rank is 1-based
+    pub fn label(&self, rank: usize) -> String {
+        format!("motif{}_{}", rank, self.consensus)
+    }
+}
+
+// Structured results written to results.json in the task result directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MotifResults {
+    pub alphabet: Alphabet,
+    pub kmer_length: usize,
+    pub revcom_mode: bool,
+    pub background: Vec<f64>,
+    pub motifs: Vec<Motif>,
+    pub clustering: Option<MotifClustering>,
+}
@@ -0,0 +1,150 @@
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use std::path::Path;
+use super::cluster::Dendrogram;
+use super::alphabet::Alphabet;
+use super::pwm::Pwm;
+
+const ROW_HEIGHT: i32 = 60;
+const MARGIN: i32 = 20;
+const TREE_WIDTH: i32 = 300;
+const COLUMN_WIDTH: i32 = 24;
+const LABEL_WIDTH: i32 = 260;
+
+// Conventional logo colors: per base for nucleotides, by chemistry for amino acids
+fn letter_color(alphabet: Alphabet, letter: u8) -> RGBColor {
+    match (alphabet, letter) {
+        (Alphabet::Dna | Alphabet::Rna, b'A') => RGBColor(0, 128, 0),
+        (Alphabet::Dna | Alphabet::Rna, b'C') => RGBColor(0, 0, 200),
+        (Alphabet::Dna | Alphabet::Rna, b'G') => RGBColor(255, 165, 0),
+        (Alphabet::Dna | Alphabet::Rna, _) => RGBColor(200, 0, 0),
+        (Alphabet::Protein, b'D' | b'E') => RGBColor(200, 0, 0),
+        (Alphabet::Protein, b'K' | b'R' | b'H') => RGBColor(0, 0, 200),
+        (Alphabet::Protein, b'S' | b'T' | b'Y' | b'C' | b'G') => RGBColor(0, 128, 0),
+        (Alphabet::Protein, b'N' | b'Q') => RGBColor(128, 0, 128),
+        (Alphabet::Protein, _) => RGBColor(0, 0, 0),
+    }
+}
+
+// Information content of a column in bits, log2 of the alphabet size for a single
+// letter and 0 for uniform
+fn column_information(row: &[f64]) -> f64 {
+    let entropy: f64 = row.iter()
+        .filter(|&&p| p > 0.0)
+        .map(|&p| -p * p.log2())
+        .sum();
+    ((row.len() as f64).log2() - entropy).max(0.0)
+}
+
+// Draw one sequence logo with its bottom-left corner at (x, baseline)
+fn draw_logo<DB: DrawingBackend>(
+    area: &DrawingArea<DB, plotters::coord::Shift>,
+    pwm: &Pwm,
+    x: i32,
+    baseline: i32,
+    height: i32,
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    let max_information = (pwm.alphabet.size() as f64).log2();
+    for (col, row) in pwm.rows.iter().enumerate() {
+        let information = column_information(row);
+        let x0 = x + col as i32 * COLUMN_WIDTH;
+
+        // Stack letters with the most probable one on top
+        let mut order: Vec<usize> = (0..row.len()).collect();
+        order.sort_by(|&a, &b| row[a].total_cmp(&row[b]));
+
+        let mut y = baseline as f64;
+        for code in order {
+            let letter = pwm.alphabet.decode(code as u64);
+            let letter_height = row[code] * information / max_information * height as f64;
+            if letter_height < 1.0 {
+                continue;
+            }
+            let top = y - letter_height;
+            let color = letter_color(pwm.alphabet, letter);
+            area.draw(&Rectangle::new(
+                [(x0 + 1, top.round() as i32), (x0 + COLUMN_WIDTH - 1, y.round() as i32)],
+                color.mix(0.35).filled(),
+            ))?;
+            if letter_height >= 10.0 {
+                let style = TextStyle::from(("sans-serif", letter_height.min(28.0)).into_font())
+                    .color(&color)
+                    .pos(Pos::new(HPos::Center, VPos::Center));
+                area.draw(&Text::new(
+                    (letter as char).to_string(),
+                    (x0 + COLUMN_WIDTH / 2, ((top + y) / 2.0).round() as i32),
+                    style,
+                ))?;
+            }
+            y = top;
+        }
+    }
+    Ok(())
+}
+
+// Render the motif dendrogram as an SVG with a sequence logo next to each leaf
+pub fn plot_motif_tree(
+    output_path: &Path,
+    dendrogram: &Dendrogram,
+    pwms: &[&Pwm],
+    labels: &[String],
+) -> Result<(), String> {
+    let leaf_order = dendrogram.leaf_order();
+    let max_width = pwms.iter().map(|pwm| pwm.width()).max().unwrap_or(0) as i32;
+    let width = 2 * MARGIN + TREE_WIDTH + max_width * COLUMN_WIDTH + LABEL_WIDTH;
+    let height = 2 * MARGIN + ROW_HEIGHT * leaf_order.len().max(1) as i32;
+
+    let root = SVGBackend::new(output_path, (width as u32, height as u32)).into_drawing_area();
+    root.fill(&WHITE).map_err(|e| e.to_string())?;
+
+    // Vertical centre of every node: leaves by drawing order, internal nodes between children
+    let mut node_y = vec![0.0f64; dendrogram.nodes.len()];
+    for (row, &leaf) in leaf_order.iter().enumerate() {
+        node_y[leaf] = (MARGIN + ROW_HEIGHT * row as i32 + ROW_HEIGHT / 2) as f64;
+    }
+    for (node, entry) in dendrogram.nodes.iter().enumerate() {
+        if let Some((left, right)) = entry.children {
+            node_y[node] = (node_y[left] + node_y[right]) / 2.0;
+        }
+    }
+
+    // Leaves sit on the right edge of the tree panel, the root on the left
+    let max_distance = dendrogram.nodes.iter()
+        .map(|node| node.distance)
+        .fold(0.0f64, f64::max)
+        .max(f64::EPSILON);
+    let node_x = |distance: f64| {
+        MARGIN + TREE_WIDTH - 10 - ((distance / max_distance) * (TREE_WIDTH - 20) as f64) as i32
+    };
+
+    for entry in &dendrogram.nodes {
+        if let Some((left, right)) = entry.children {
+            let x = node_x(entry.distance);
+            let (y_left, y_right) = (node_y[left] as i32, node_y[right] as i32);
+            let line = |points: Vec<(i32, i32)>| PathElement::new(points, BLACK.stroke_width(2));
+            root.draw(&line(vec![(x, y_left), (x, y_right)])).map_err(|e| e.to_string())?;
+            for (child, y) in [(left, y_left), (right, y_right)] {
+                let child_x = node_x(dendrogram.nodes[child].distance);
+                root.draw(&line(vec![(x, y), (child_x, y)])).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    let logo_x = MARGIN + TREE_WIDTH;
+    for &leaf in &leaf_order {
+        let centre = node_y[leaf] as i32;
+        draw_logo(&root, pwms[leaf], logo_x, centre + ROW_HEIGHT / 2 - 6, ROW_HEIGHT - 12)
+            .map_err(|e| e.to_string())?;
+        let style = TextStyle::from(("sans-serif", 14).into_font())
+            .pos(Pos::new(HPos::Left, VPos::Center));
+        root.draw(&Text::new(
+            labels[leaf].clone(),
+            (logo_x + max_width * COLUMN_WIDTH + 10, centre),
+            style,
+        ))
+        .map_err(|e| e.to_string())?;
+    }
+
+    root.present().map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::sync::OnceLock;
+
+// Hamming-ball definition for one k-mer length
+#[derive(Debug, Clone)]
+pub struct MotifDef {
+    pub kmer_len: usize,
+    pub max_ham_dist: u32,
+}
+
+const DEFAULT_MOTIF_DEF_TABLE: &str = include_str!("../default_motif_def_table.csv");
+
+// Largest chance a uniformly random k-mer may have of falling inside a Hamming ball wider than
+// an exact match; in default_motif_def_table.csv only rows with max_ham_dist 0, such as the
+// 3-mer row at 0.0156, have a p_uniform above it
+const MAX_BALL_PROBABILITY: f64 = 0.006;
+
+fn parse_motif_def_table(table: &str) -> Vec<MotifDef> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim().trim_matches('"')).collect();
+            if fields.len() < 2 {
+                return None;
+            }
+            Some(MotifDef {
+                kmer_len: fields[0].parse().ok()?,
+                max_ham_dist: fields[1].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+// Probability that a uniformly random k-mer lies within max_ham_dist of a fixed k-mer
+fn ball_probability(kmer_len: usize, max_ham_dist: u32, alphabet_size: usize) -> f64 {
+    let mismatch = (alphabet_size - 1) as f64;
+    let mut binomial = 1.0;
+    let mut volume = 0.0;
+    for i in 0..=max_ham_dist as usize {
+        if i > 0 {
+            binomial *= (kmer_len + 1 - i) as f64 / i as f64;
+        }
+        volume += binomial * mismatch.powi(i as i32);
+    }
+    volume / (alphabet_size as f64).powi(kmer_len as i32)
+}
+
+// Widest Hamming ball that a uniformly random k-mer falls into rarely enough
+fn computed_motif_def(kmer_len: usize, alphabet_size: usize) -> MotifDef {
+    let mut max_ham_dist = 0;
+    while (max_ham_dist as usize) < kmer_len
+        && ball_probability(kmer_len, max_ham_dist + 1, alphabet_size) <= MAX_BALL_PROBABILITY
+    {
+        max_ham_dist += 1;
+    }
+    MotifDef { kmer_len, max_ham_dist }
+}
+
+// Look up the Hamming-ball definition for a k-mer length and alphabet size
+// DNA uses the built-in table, other alphabets apply the same rule to their size
+pub fn motif_def(kmer_len: usize, alphabet_size: usize) -> Option<MotifDef> {
+    static TABLE: OnceLock<Vec<MotifDef>> = OnceLock::new();
+    if kmer_len == 0 || alphabet_size < 2 {
+        return None;
+    }
+    if alphabet_size == 4 {
+        return TABLE
+            .get_or_init(|| parse_motif_def_table(DEFAULT_MOTIF_DEF_TABLE))
+            .iter()
+            .find(|def| def.kmer_len == kmer_len)
+            .cloned();
+    }
+    Some(computed_motif_def(kmer_len, alphabet_size))
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use super::alphabet::Alphabet;
+
+// Position weight matrix, one row of letter probabilities per motif position
+// Rows are ordered like the alphabet's letters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pwm {
+    pub alphabet: Alphabet,
+    pub rows: Vec<Vec<f64>>,
+}
+
+impl Pwm {
+    // Normalize per-position letter counts into probabilities, adding a pseudocount to every cell
+    pub fn from_counts(alphabet: Alphabet, counts: &[Vec<f64>], pseudocount: f64) -> Self {
+        let size = alphabet.size();
+        let rows = counts
+            .iter()
+            .map(|row| {
+                let total: f64 = row.iter().sum::<f64>() + size as f64 * pseudocount;
+                if total > 0.0 {
+                    row.iter().map(|count| (count + pseudocount) / total).collect()
+                } else {
+                    vec![1.0 / size as f64; size]
+                }
+            })
+            .collect();
+        Self { alphabet, rows }
+    }
+
+    pub fn width(&self) -> usize {
+        self.rows.len()
+    }
+
+    // Most probable letter at each position
+    pub fn consensus(&self) -> String {
+        self.rows
+            .iter()
+            .map(|row| {
+                let best = (0..row.len())
+                    .max_by(|&a, &b| row[a].total_cmp(&row[b]))
+                    .unwrap_or(0);
+                self.alphabet.decode(best as u64) as char
+            })
+            .collect()
+    }
+
+    // PWM of the opposite strand, only meaningful for DNA
+    pub fn revcom(&self) -> Self {
+        let rows = self
+            .rows
+            .iter()
+            .rev()
+            .map(|row| row.iter().rev().copied().collect())
+            .collect();
+        Self { alphabet: self.alphabet, rows }
+    }
+}
@@ -0,0 +1,80 @@
+use super::pwm::Pwm;
+
+// Sites must score at least this far from the worst to the best attainable score
+pub const DEFAULT_MIN_RELATIVE_SCORE: f64 = 0.8;
+
+// Best-scoring occurrence of a motif in one sequence
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub sequence: usize,
+    pub offset: usize,
+    pub reverse: bool,
+    pub score: f64,
+    pub relative_score: f64,
+}
+
+// Log-odds matrix of a PWM against background letter frequencies
+fn log_odds(pwm: &Pwm, background: &[f64]) -> Vec<Vec<f64>> {
+    pwm.rows
+        .iter()
+        .map(|row| row.iter().zip(background).map(|(p, bg)| (p / bg).ln()).collect())
+        .collect()
+}
+
+// Scan every sequence for its best motif occurrence and keep those whose score is at
+// least min_relative_score of the way from the lowest to the highest attainable score
+// The opposite strand is scanned only for DNA in revcom mode
+pub fn scan_best_sites(
+    pwm: &Pwm,
+    background: &[f64],
+    sequences: &[Vec<u8>],
+    revcom_mode: bool,
+    min_relative_score: f64,
+) -> Vec<Site> {
+    let alphabet = pwm.alphabet;
+    let width = pwm.width();
+    let forward = log_odds(pwm, background);
+    let reverse = log_odds(&pwm.revcom(), background);
+    let strands: &[bool] = if revcom_mode && alphabet.supports_revcom() { &[false, true] } else { &[false] };
+
+    let max_score: f64 = forward.iter().map(|row| row.iter().copied().fold(f64::MIN, f64::max)).sum();
+    let min_score: f64 = forward.iter().map(|row| row.iter().copied().fold(f64::MAX, f64::min)).sum();
+    let range = (max_score - min_score).max(f64::EPSILON);
+
+    let mut sites = Vec::new();
+    if width == 0 {
+        return sites;
+    }
+
+    for (index, seq) in sequences.iter().enumerate() {
+        let codes: Vec<Option<u64>> = seq.iter().map(|&symbol| alphabet.encode(symbol)).collect();
+        let mut best: Option<Site> = None;
+
+        for (offset, window) in codes.windows(width).enumerate() {
+            for &is_reverse in strands {
+                let matrix = if is_reverse { &reverse } else { &forward };
+                let score: Option<f64> = window
+                    .iter()
+                    .zip(matrix)
+                    .map(|(code, row)| code.map(|code| row[code as usize]))
+                    .sum();
+                let Some(score) = score else { continue };
+                if best.as_ref().is_none_or(|site| score > site.score) {
+                    best = Some(Site {
+                        sequence: index,
+                        offset,
+                        reverse: is_reverse,
+                        score,
+                        relative_score: (score - min_score) / range,
+                    });
+                }
+            }
+        }
+
+        if let Some(site) = best.filter(|site| site.relative_score >= min_relative_score) {
+            sites.push(site);
+        }
+    }
+
+    sites
+}
@@ -0,0 +1,10 @@
+// Shared by the web server (main.rs) and the standalone worker (bin/kmap-worker.rs)
+pub mod models;
+pub mod handlers;
+pub mod services;
+pub mod middleware;
+pub mod worker;
+pub mod kmap_algorithms;
+pub mod config;
+pub mod errors;
+pub mod shutdown;
@@ -1,31 +1,25 @@
-mod models;
-mod handlers;
-mod services;
-mod middleware;
-mod worker;
-mod kmap_algorithms;
-mod config;
-mod errors;
-
 use axum::{
     routing::{get, post},
     Router,
     extract::DefaultBodyLimit,
-    middleware::from_fn,
+    middleware::{from_fn, from_fn_with_state},
 };
+use tokio::sync::watch;
+use tokio::time::Duration;
 use tower_http::{
     services::ServeDir,
     limit::RequestBodyLimitLayer,
 };
 use tower_sessions::{MemoryStore, SessionManagerLayer};
 use tower_sessions::cookie::SameSite;
-use std::sync::Arc;
-use tokio::sync::Semaphore;
-use crate::{
+use axum_kmap::{
+    handlers,
+    middleware,
+    worker,
+    shutdown,
     services::RedisService,
     config::Config,
 };
-use tracing_subscriber;
 
 #[tokio::main]
 async fn main() {
@@ -36,30 +30,20 @@ async fn main() {
     let config = Config::load().expect("Failed to load configuration");
     let config_state = config.clone();
 
-    // Initialize Redis client
-    let redis_client = if config.redis.sentinel_enabled {
-        Arc::new(redis::Client::open(
-            config.redis.sentinel_url.expect("Sentinel URL not configured")
-        ).expect("Failed to connect to Redis Sentinel"))
-    } else {
-        Arc::new(redis::Client::open(config.redis.url)
-            .expect("Failed to connect to Redis"))
-    };
-    
     // Initialize RedisService
-    let redis_service = RedisService::new(redis_client.clone());
+    let redis_service = RedisService::from_config(&config.redis)
+        .expect("Failed to connect to Redis");
     
-    // Initialize worker pool with configured values
-    let semaphore = Arc::new(Semaphore::new(config.worker.max_concurrent_tasks));
+    // Run the worker pool in this process unless dedicated kmap-worker nodes do the processing
+    let worker_pool = if config.worker.in_process {
+        Some(worker::spawn_worker_pool(redis_service.clone(), &config))
+    } else {
+        tracing::info!("In-process workers disabled, tasks are processed by kmap-worker");
+        None
+    };
 
-    // Initialize worker pool
-    for _ in 0..config.worker.worker_count {
-        let redis_service_worker = redis_service.clone();
-        let semaphore_worker = semaphore.clone();
-        tokio::spawn(async move {
-            worker::worker_process(redis_service_worker, semaphore_worker).await;
-        });
-    }
+    // Raised on SIGTERM/SIGINT so that new uploads are turned away while running tasks finish
+    let (shutting_down, shutting_down_rx) = watch::channel(false);
     
     // Session store setup
     let session_store = MemoryStore::default();
@@ -68,6 +52,12 @@ async fn main() {
         .with_same_site(SameSite::Lax)
         .with_name("session");
 
+    // Uploads may carry a whole batch of files, so they get a larger body limit than other requests
+    // The handler still holds every single file to the per-file limit
+    let upload_routes = Router::new()
+        .route("/process", post(handlers::process_upload))
+        .layer(RequestBodyLimitLayer::new(config.upload.max_request_size));
+
     // Create router with all routes
     let app = Router::new()
         // Auth routes
@@ -78,25 +68,41 @@ async fn main() {
         
         // Task routes
         .route("/upload", get(handlers::serve_upload_page))
-        .route("/process", post(handlers::process_upload))
+        .route("/estimate", get(handlers::estimate_task))
         .route("/status/:task_id", get(handlers::get_task_status))
         .route("/download/:task_id", get(handlers::download_results))
         
+        // Batch routes
+        .route("/batch/:batch_id", get(handlers::view_batch))
+        .route("/batch/:batch_id/status", get(handlers::get_batch_status))
+        .route("/batch/:batch_id/download", get(handlers::download_batch))
+        
         // Dashboard routes
         .route("/process/:task_id", get(handlers::view_process))
         .route("/delete/:task_id", get(handlers::delete_task))
+        .route("/cancel/:task_id", get(handlers::cancel_task))
         .route("/user", get(handlers::serve_user_dashboard))
         
+        // Admin routes; actions that change state are POST only, which the Lax session
+        // cookie is not sent with from other sites
+        .route("/admin/dead_letter", get(handlers::list_dead_letter_tasks))
+        .route("/admin/dead_letter/:task_id/requeue", post(handlers::requeue_dead_letter_task))
+        .route("/admin/users/:username/plan/:plan", post(handlers::assign_plan))
+        
         // Static files
         .nest_service("/static", ServeDir::new("static"))
         
+        // Other requests carry at most one file
+        .layer(RequestBodyLimitLayer::new(config.upload.max_file_size))
+        .merge(upload_routes)
+        
         // Add middleware
         .layer(from_fn(middleware::require_auth))
+        .layer(from_fn_with_state(shutting_down_rx, middleware::reject_uploads_during_shutdown))
         .layer(session_layer)
         
-        // File upload limits from config
+        // Body size limits from config are applied per route above
         .layer(DefaultBodyLimit::disable())
-        .layer(RequestBodyLimitLayer::new(config.upload.max_file_size))
         
         // Add state
         .with_state((redis_service, config_state));
@@ -108,7 +114,17 @@ async fn main() {
     .await
     .expect("Failed to bind server");
 
+    // On shutdown: stop accepting uploads, let the workers wind down, then stop serving
+    let grace_period = Duration::from_secs(config.worker.shutdown_grace_period);
     axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(async move {
+            shutdown::shutdown_signal().await;
+            tracing::info!("Shutting down, no longer accepting uploads");
+            shutting_down.send_replace(true);
+            if let Some(worker_pool) = worker_pool {
+                worker_pool.shutdown(grace_period).await;
+            }
+        })
         .await
         .expect("Failed to start server");
 }
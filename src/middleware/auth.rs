@@ -5,7 +5,6 @@ use axum::{
     body::Body,
 };
 use tower_sessions::Session;
-use tracing;
 
 pub async fn require_auth(
     session: Session,
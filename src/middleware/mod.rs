@@ -1,3 +1,5 @@
 mod auth;
+mod shutdown;
 
-pub use auth::require_auth; 
\ No newline at end of file
+pub use auth::require_auth;
+pub use shutdown::reject_uploads_during_shutdown;
@@ -0,0 +1,27 @@
+use axum::{
+    middleware::Next,
+    response::{IntoResponse, Response},
+    extract::{Request, State},
+    body::Body,
+    http::{Method, StatusCode},
+};
+use tokio::sync::watch;
+
+// Turn away new uploads once the server is shutting down; everything else keeps working
+// so users can follow their tasks until the server stops
+pub async fn reject_uploads_during_shutdown(
+    State(shutting_down): State<watch::Receiver<bool>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if req.method() == Method::POST && req.uri().path() == "/process" && *shutting_down.borrow() {
+        tracing::warn!("Rejected upload, server is shutting down");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The server is restarting, please submit your task again in a few minutes",
+        )
+            .into_response();
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use super::forms::ProcessForm;
+use super::task::TaskStatus;
+
+// Several inputs submitted together with the same parameters; each runs as its own task
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchInfo {
+    pub batch_id: String,
+    pub user: String,
+    pub task_ids: Vec<String>,
+    pub params: ProcessForm,
+    pub submission_time: DateTime<Utc>,
+    #[serde(default)]
+    pub skipped: Vec<String>,  // Inputs that could not be submitted, with the reason
+}
+
+// Overall state of a batch, derived from the states of its tasks
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BatchStatus {
+    Queued,      // No task has started yet
+    Processing,  // Some tasks have not finished
+    Completed,   // Every task has finished and at least one completed
+    Failed,      // Every task has finished and none completed
+}
+
+impl BatchStatus {
+    pub fn from_task_statuses<'a>(statuses: impl IntoIterator<Item = &'a TaskStatus>) -> Self {
+        let (mut total, mut queued, mut running, mut completed) = (0, 0, 0, 0);
+        for status in statuses {
+            total += 1;
+            match status {
+                TaskStatus::Queued => queued += 1,
+                TaskStatus::Processing => running += 1,
+                TaskStatus::Completed => completed += 1,
+                TaskStatus::Failed | TaskStatus::Cancelled => {}
+            }
+        }
+
+        if total > 0 && queued == total {
+            BatchStatus::Queued
+        } else if queued + running > 0 {
+            BatchStatus::Processing
+        } else if completed > 0 {
+            BatchStatus::Completed
+        } else {
+            BatchStatus::Failed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_of(statuses: &[TaskStatus]) -> BatchStatus {
+        BatchStatus::from_task_statuses(statuses)
+    }
+
+    #[test]
+    fn batch_status_from_tasks() {
+        use TaskStatus::*;
+        assert_eq!(status_of(&[Queued, Queued]), BatchStatus::Queued);
+        assert_eq!(status_of(&[Queued, Completed]), BatchStatus::Processing);
+        assert_eq!(status_of(&[Processing, Failed]), BatchStatus::Processing);
+        assert_eq!(status_of(&[Completed, Failed, Cancelled]), BatchStatus::Completed);
+        assert_eq!(status_of(&[Failed, Cancelled]), BatchStatus::Failed);
+        assert_eq!(status_of(&[]), BatchStatus::Failed);
+    }
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+// Outcome of looking a task up in the result cache, kept on the task
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheLookup {
+    pub key: String,
+    pub hit: bool,
+    pub source_task_id: Option<String>,  // Task whose results were reused, on a hit
+}
+
+// Cache entry pointing at the results of a completed task
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedResult {
+    pub task_id: String,
+    pub result_path: String,
+    pub result: HashMap<String, u32>,
+    pub seed: Option<u64>,
+    pub created_at: DateTime<Utc>,
+}
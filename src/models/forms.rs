@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use crate::kmap_algorithms::alphabet::Alphabet;
 
 #[derive(Debug, Deserialize)]
 pub struct LoginForm {
@@ -13,10 +14,24 @@ pub struct RegisterForm {
     pub confirm_password: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct ProcessForm {
     pub n_trial: u32,
     pub top_k: u32,
     pub revcom_mode: bool,
     pub min_ham_dist_mode: bool,
+    #[serde(default)]
+    pub em_refine: bool,
+    #[serde(default)]
+    pub em_max_iter: u32,
+    #[serde(default)]
+    pub alphabet: Alphabet,
+    #[serde(default)]
+    pub genome: Option<String>,      // Reference genome name for BED uploads
+    #[serde(default)]
+    pub recenter: bool,              // Centre BED windows on the narrowPeak summit
+    #[serde(default)]
+    pub window_width: u32,           // Fixed BED window width, 0 keeps each interval's width
+    #[serde(default)]
+    pub seed: Option<u64>,           // Recorded in the run manifest, generated at submission if absent
 } 
\ No newline at end of file
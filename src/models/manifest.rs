@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use super::forms::ProcessForm;
+
+// Everything needed to reproduce a run, written to manifest.json in the result directory
+// Submitting it again with the same input file reproduces the output files byte for byte
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunManifest {
+    pub version: String,
+    pub seed: u64,
+    pub input_filename: String,
+    pub input_sha256: String,
+    pub params: ProcessForm,
+    // Stage graph of the pipeline that produced the results
+    #[serde(default)]
+    pub stages: Vec<StageInfo>,
+}
+
+// Steps of the processing pipeline; each checkpoints its artifacts in the result directory
+// so that a retried or resumed task skips the stages it already completed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineStage {
+    ExtractSequences,
+    CountKmers,
+    DiscoverMotifs,
+    ClusterMotifs,
+    ScanSites,
+}
+
+impl PipelineStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PipelineStage::ExtractSequences => "extract_sequences",
+            PipelineStage::CountKmers => "count_kmers",
+            PipelineStage::DiscoverMotifs => "discover_motifs",
+            PipelineStage::ClusterMotifs => "cluster_motifs",
+            PipelineStage::ScanSites => "scan_sites",
+        }
+    }
+}
+
+// One node of the stage graph: the stages it reads from and the files it writes,
+// relative to the result directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StageInfo {
+    pub stage: PipelineStage,
+    pub depends_on: Vec<PipelineStage>,
+    pub artifacts: Vec<String>,
+}
@@ -1,7 +1,15 @@
 mod user;
 mod forms;
 mod task;
+mod manifest;
+mod worker;
+mod cache;
+mod batch;
 
-pub use user::User;
+pub use user::{User, QuotaLedgerEntry};
 pub use forms::{LoginForm, RegisterForm, ProcessForm};
-pub use task::{TaskInfo, TaskStatus}; 
\ No newline at end of file
+pub use task::{TaskInfo, TaskStatus, TaskPriority, TaskProgress, TaskError, TaskErrorCode, RuntimeSample};
+pub use manifest::{RunManifest, PipelineStage, StageInfo};
+pub use worker::WorkerHeartbeat;
+pub use cache::{CacheLookup, CachedResult};
+pub use batch::{BatchInfo, BatchStatus};
\ No newline at end of file
@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use super::forms::ProcessForm;
+use super::cache::CacheLookup;
 
 // Define task status enum
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -10,13 +11,49 @@ pub enum TaskStatus {
     Processing,
     Completed,
     Failed,
+    Cancelled,
+}
+
+// Orders a user's own queued tasks; users are always served in turn whatever the priority
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TaskPriority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl TaskPriority {
+    // Highest first, the order in which a user's queues are served
+    pub const ALL: [TaskPriority; 3] = [TaskPriority::High, TaskPriority::Normal, TaskPriority::Low];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskPriority::High => "high",
+            TaskPriority::Normal => "normal",
+            TaskPriority::Low => "low",
+        }
+    }
+}
+
+impl std::str::FromStr for TaskPriority {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "high" => Ok(TaskPriority::High),
+            "normal" => Ok(TaskPriority::Normal),
+            "low" => Ok(TaskPriority::Low),
+            _ => Err(format!("unknown priority '{}', expected high, normal or low", value)),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct TaskInfo {
     pub task_id: String,
     pub user: String,
-    pub fasta_path: String,
+    pub fasta_path: String,  // Uploaded input: FASTA, or BED when reference_fasta is set
     pub filename: String,
     pub status: TaskStatus,
     pub params: ProcessForm,
@@ -24,4 +61,81 @@ pub struct TaskInfo {
     pub result_path: String,
     pub submission_time: DateTime<Utc>,
     pub completion_time: Option<DateTime<Utc>>,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub reference_fasta: Option<String>,
+    #[serde(default)]
+    pub input_sha256: Option<String>,
+    #[serde(default)]
+    pub attempts: u32,       // Number of times a worker has started this task
+    #[serde(default)]
+    pub start_time: Option<DateTime<Utc>>,  // When a worker last started this task
+    #[serde(default)]
+    pub error: Option<TaskError>,  // Why the task failed, for Failed tasks
+    #[serde(default)]
+    pub reserved_quota: u64,  // Seconds reserved from the user's quota while running
+    #[serde(default)]
+    pub priority: TaskPriority,
+    #[serde(default)]
+    pub cache: Option<CacheLookup>,  // Result cache hit or miss, None for inputs that cannot be cached
+    #[serde(default)]
+    pub batch_id: Option<String>,  // Batch the task was submitted with
+    #[serde(default)]
+    pub storage_bytes: u64,  // Size of the results, charged to the user's storage quota
+}
+
+impl TaskInfo {
+    // When the results of a finished task are deleted, None while it has not finished
+    pub fn expires_at(&self, result_ttl: u64) -> Option<DateTime<Utc>> {
+        match self.status {
+            TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Cancelled => self
+                .completion_time
+                .map(|completion_time| completion_time + chrono::Duration::seconds(result_ttl as i64)),
+            TaskStatus::Queued | TaskStatus::Processing => None,
+        }
+    }
+}
+
+// Broad cause of a failure, telling users whether to fix their input or ask for more quota
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TaskErrorCode {
+    Timeout,
+    QuotaExceeded,
+    FileNotFound,
+    InvalidKmer,
+    Processing,
+    Internal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskError {
+    pub code: TaskErrorCode,
+    pub message: String,  // Safe to show to the user
+    pub timestamp: DateTime<Utc>,
+}
+
+impl TaskError {
+    pub fn new(code: TaskErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+// Work done by a completed task and how long it took, used to calibrate run time estimates
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuntimeSample {
+    pub work_units: f64,
+    pub seconds: u64,
+}
+
+// Live progress of a running task, kept under its own Redis key so the worker
+// can update it without rewriting the task record
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskProgress {
+    pub stage: String,
+    pub percent: f64,             // 0 to 100, over the whole pipeline
+    pub eta_seconds: Option<u64>, // Unknown until some progress was made
+    pub updated_at: DateTime<Utc>,
+}
@@ -1,10 +1,43 @@
 use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub username: String,
     pub password_hash: String,  // We'll store hashed passwords, not plain text
     pub tasks: Vec<String>,     // List of task IDs
-    pub quota: u64,            // Longest processing time
+    #[serde(default)]
+    pub active_tasks: Vec<String>,  // Queued and running tasks, limited by the plan
     pub used_quota: u64,       // Current usage time
-}
\ No newline at end of file
+    #[serde(default)]
+    pub reserved_quota: u64,   // Held by running tasks, settled when they finish
+    #[serde(default)]
+    pub used_storage: u64,     // Bytes taken by results of finished tasks
+    #[serde(default)]
+    pub plan: Option<String>,  // Named plan from the configuration, None for the defaults
+    #[serde(default)]
+    pub quota_reset_at: Option<DateTime<Utc>>,  // When used_quota is next reset, None if never
+}
+
+impl User {
+    // Quota of the user's plan that is neither used nor reserved by running tasks
+    pub fn available_quota(&self, quota: u64) -> u64 {
+        quota
+            .saturating_sub(self.used_quota)
+            .saturating_sub(self.reserved_quota)
+    }
+
+    // Stop counting a task that finished, or was removed, against the active task limit
+    pub fn release_active_task(&mut self, task_id: &str) {
+        self.active_tasks.retain(|id| id != task_id);
+    }
+}
+
+// One settled task run in a user's quota ledger
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QuotaLedgerEntry {
+    pub task_id: String,
+    pub reserved: u64,  // in seconds
+    pub charged: u64,   // in seconds, never more than reserved
+    pub timestamp: DateTime<Utc>,
+}
@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// Liveness record a worker keeps refreshing in Redis; it expires when the worker stops
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkerHeartbeat {
+    pub worker_id: String,
+    pub task_id: Option<String>,  // Task the worker currently holds, if any
+    pub timestamp: DateTime<Utc>,
+}
@@ -0,0 +1,437 @@
+// Zip and tar.gz support for batches and downloads
+// Zip uploads are read for batches; downloads are streamed as zip or tar.gz while they are written
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use bytes::Bytes;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::Stream;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use crate::worker::CHECKPOINT_DIR;
+
+// Downloads are sent in chunks of about this size
+const STREAM_CHUNK_LEN: usize = 64 * 1024;
+// Chunks written ahead of the client before the writer waits
+const STREAM_CHUNKS_BUFFERED: usize = 16;
+
+// A file read from a zip archive
+pub struct ZipEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// Read every file of a zip archive held in memory, skipping directories
+// Archives of more than max_entries entries, or whose files would decompress to more than
+// max_entry_size bytes each or max_total_size bytes together, are rejected
+pub fn read_zip(archive: &[u8], max_entry_size: u64, max_total_size: u64, max_entries: usize) -> io::Result<Vec<ZipEntry>> {
+    let mut archive = ZipArchive::new(Cursor::new(archive))?;
+    if archive.len() > max_entries {
+        return Err(invalid(format!("Zip archive has more than {} entries", max_entries)));
+    }
+
+    let mut entries = Vec::with_capacity(archive.len());
+    let mut total_size: u64 = 0;
+    for index in 0..archive.len() {
+        let file = archive.by_index(index)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_string();
+        // The declared size may lie, so the read itself is capped too
+        if file.size() > max_entry_size {
+            return Err(invalid(format!("Zip entry {} is larger than {} bytes", name, max_entry_size)));
+        }
+
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.take(max_entry_size.saturating_add(1)).read_to_end(&mut data)?;
+        if data.len() as u64 > max_entry_size {
+            return Err(invalid(format!("Zip entry {} is larger than {} bytes", name, max_entry_size)));
+        }
+        total_size += data.len() as u64;
+        if total_size > max_total_size {
+            return Err(invalid(format!("Zip archive unpacks to more than {} bytes", max_total_size)));
+        }
+        entries.push(ZipEntry { name, data });
+    }
+
+    Ok(entries)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            _ => Err(format!("Unknown archive format: {}", s)),
+        }
+    }
+}
+
+// A file to put in an archive, read from disk or held in memory
+pub struct ArchiveEntry {
+    pub name: String,  // Path inside the archive, with forward slashes
+    pub source: EntrySource,
+}
+
+pub enum EntrySource {
+    File(PathBuf),
+    Data(Vec<u8>),
+}
+
+// Checksum of one archived file, listed in the archive's manifest.json
+#[derive(Serialize)]
+struct ArtifactChecksum {
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+// List the files under a directory as archive entries named <prefix>/<relative path>, in name order
+// Checkpoints are intermediate artifacts of the run, not results, so they are left out
+pub fn collect_files(dir: &Path, prefix: &str) -> io::Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    let mut names: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    names.sort_by_key(|entry| entry.file_name());
+    for entry in names {
+        if entry.file_name() == CHECKPOINT_DIR {
+            continue;
+        }
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            entries.extend(collect_files(&entry.path(), &name)?);
+        } else {
+            entries.push(ArchiveEntry { name, source: EntrySource::File(entry.path()) });
+        }
+    }
+    Ok(entries)
+}
+
+// Write an archive on a blocking thread and hand it out chunk by chunk as it is written
+// manifest is written last, as <manifest_name>, with the checksums of all entries added under "artifacts"
+// A failure ends the stream with an error, so the client sees an aborted download rather than a truncated archive
+pub fn stream_archive(
+    format: ArchiveFormat,
+    entries: Vec<ArchiveEntry>,
+    manifest: serde_json::Value,
+    manifest_name: String,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (sender, receiver) = mpsc::channel(STREAM_CHUNKS_BUFFERED);
+
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter { sender: sender.clone(), buffer: Vec::with_capacity(STREAM_CHUNK_LEN) };
+        let written = write_archive(&mut writer, format, &entries, manifest, &manifest_name)
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            tracing::error!("Failed to write {} archive: {}", format.extension(), e);
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+fn write_archive(
+    writer: impl Write,
+    format: ArchiveFormat,
+    entries: &[ArchiveEntry],
+    mut manifest: serde_json::Value,
+    manifest_name: &str,
+) -> io::Result<()> {
+    let mut archive = match format {
+        ArchiveFormat::Zip => ArchiveWriter::Zip(ZipWriter::new_stream(writer)),
+        ArchiveFormat::TarGz => {
+            ArchiveWriter::TarGz(tar::Builder::new(GzEncoder::new(writer, Compression::default())))
+        }
+    };
+
+    let mut checksums = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let checksum = match &entry.source {
+            EntrySource::File(path) => {
+                let file = File::open(path)?;
+                let metadata = file.metadata()?;
+                let modified = metadata.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+                archive.append(&entry.name, file, metadata.len(), modified)?
+            }
+            EntrySource::Data(data) => archive.append(&entry.name, data.as_slice(), data.len() as u64, Utc::now())?,
+        };
+        checksums.push(checksum);
+    }
+
+    if !manifest.is_object() {
+        manifest = serde_json::json!({});
+    }
+    manifest["artifacts"] = serde_json::to_value(&checksums).map_err(io::Error::other)?;
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+    archive.append(manifest_name, manifest.as_slice(), manifest.len() as u64, Utc::now())?;
+
+    archive.finish()
+}
+
+enum ArchiveWriter<W: Write> {
+    Zip(ZipWriter<zip::write::StreamWriter<W>>),
+    TarGz(tar::Builder<GzEncoder<W>>),
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    // Copy one file of size bytes into the archive, hashing it on the way through
+    fn append(&mut self, name: &str, data: impl Read, size: u64, modified: DateTime<Utc>) -> io::Result<ArtifactChecksum> {
+        let mut reader = HashingReader { inner: data.take(size), hasher: Sha256::new(), len: 0 };
+        match self {
+            ArchiveWriter::Zip(zip) => {
+                let options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .last_modified_time(zip_date_time(modified))
+                    .large_file(size >= u32::MAX as u64);
+                zip.start_file(name, options)?;
+                io::copy(&mut reader, zip)?;
+            }
+            ArchiveWriter::TarGz(tar) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(size);
+                header.set_mode(0o644);
+                header.set_mtime(modified.timestamp().max(0) as u64);
+                tar.append_data(&mut header, name, &mut reader)?;
+            }
+        }
+
+        // A file that shrank while it was archived would leave its entry short of the size in its header
+        if reader.len != size {
+            return Err(io::Error::other(format!("{} changed while it was archived", name)));
+        }
+        Ok(ArtifactChecksum {
+            path: name.to_string(),
+            size,
+            sha256: format!("{:x}", reader.hasher.finalize()),
+        })
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip.finish()?.flush(),
+            ArchiveWriter::TarGz(tar) => tar.into_inner()?.finish()?.flush(),
+        }
+    }
+}
+
+// Passes a file through while computing its SHA-256 and length
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.len += read as u64;
+        Ok(read)
+    }
+}
+
+// Zip timestamps use the MS-DOS format, which starts in 1980
+fn zip_date_time(time: DateTime<Utc>) -> zip::DateTime {
+    zip::DateTime::from_date_and_time(
+        time.year().clamp(1980, 2107) as u16,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+// Sends written bytes to the download stream in chunks, waiting while the client is behind
+struct ChannelWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= STREAM_CHUNK_LEN {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buffer, Vec::with_capacity(STREAM_CHUNK_LEN)));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Download was closed by the client"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    fn entries() -> Vec<ArchiveEntry> {
+        vec![
+            ArchiveEntry { name: "run/motifs.txt".into(), source: EntrySource::Data(b"ACGTACGT\n".repeat(1000)) },
+            ArchiveEntry { name: "run/empty.txt".into(), source: EntrySource::Data(Vec::new()) },
+            ArchiveEntry {
+                name: format!("run/{}/logo.svg", "nested".repeat(20)),
+                source: EntrySource::Data(b"<svg/>".to_vec()),
+            },
+        ]
+    }
+
+    fn write(format: ArchiveFormat, entries: &[ArchiveEntry]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        write_archive(&mut archive, format, entries, serde_json::json!({"task_id": "t1"}), "run/manifest.json").unwrap();
+        archive
+    }
+
+    fn check_manifest(files: &[(String, Vec<u8>)]) {
+        let (_, manifest) = files.iter().find(|(name, _)| name == "run/manifest.json").unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(manifest).unwrap();
+        assert_eq!(manifest["task_id"], "t1");
+        let artifacts = manifest["artifacts"].as_array().unwrap();
+        assert_eq!(artifacts.len(), files.len() - 1);
+        for artifact in artifacts {
+            let (_, data) = files.iter().find(|(name, _)| name == artifact["path"].as_str().unwrap()).unwrap();
+            assert_eq!(artifact["size"], data.len() as u64);
+            assert_eq!(artifact["sha256"], format!("{:x}", Sha256::digest(data)));
+        }
+    }
+
+    #[test]
+    fn zip_round_trip() {
+        let entries = entries();
+        let files: Vec<(String, Vec<u8>)> = read_zip(&write(ArchiveFormat::Zip, &entries), 1 << 20, 1 << 20, 10)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.name, entry.data))
+            .collect();
+
+        assert_eq!(files.len(), entries.len() + 1);
+        for (entry, (name, data)) in entries.iter().zip(&files) {
+            let EntrySource::Data(expected) = &entry.source else { unreachable!() };
+            assert_eq!(&entry.name, name);
+            assert_eq!(expected, data);
+        }
+        check_manifest(&files);
+    }
+
+    #[test]
+    fn tar_gz_round_trip() {
+        let entries = entries();
+        let archive = write(ArchiveFormat::TarGz, &entries);
+        let mut tar = tar::Archive::new(GzDecoder::new(archive.as_slice()));
+        let files: Vec<(String, Vec<u8>)> = tar
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (name, data)
+            })
+            .collect();
+
+        assert_eq!(files.len(), entries.len() + 1);
+        for (entry, (name, data)) in entries.iter().zip(&files) {
+            let EntrySource::Data(expected) = &entry.source else { unreachable!() };
+            assert_eq!(&entry.name, name);
+            assert_eq!(expected, data);
+        }
+        check_manifest(&files);
+    }
+
+    #[test]
+    fn files_are_streamed_from_disk() {
+        let dir = std::env::temp_dir().join(format!("kmap-archive-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("logos")).unwrap();
+        fs::write(dir.join("summary.txt"), b"summary").unwrap();
+        fs::write(dir.join("logos/m1.svg"), b"<svg/>").unwrap();
+        fs::create_dir_all(dir.join(CHECKPOINT_DIR)).unwrap();
+        fs::write(dir.join(CHECKPOINT_DIR).join("motifs.json"), b"[]").unwrap();
+
+        // Checkpoints are not part of the results
+        let entries = collect_files(&dir, "run").unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["run/logos/m1.svg", "run/summary.txt"]);
+
+        let files = read_zip(&write(ArchiveFormat::Zip, &entries), 1 << 20, 1 << 20, 10).unwrap();
+        assert_eq!(files[0].data, b"<svg/>");
+        assert_eq!(files[1].data, b"summary");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_zip_rejects_large_entries() {
+        let archive = write(ArchiveFormat::Zip, &entries());
+        let error = read_zip(&archive, 100, 1 << 20, 10).err().unwrap();
+        assert!(error.to_string().contains("run/motifs.txt"));
+    }
+
+    #[test]
+    fn read_zip_caps_total_size_and_entries() {
+        let archive = write(ArchiveFormat::Zip, &entries());
+        // motifs.txt alone is 9000 bytes
+        assert!(read_zip(&archive, 1 << 20, 8999, 10).is_err());
+        assert!(read_zip(&archive, 1 << 20, 1 << 20, 3).is_err());
+        assert_eq!(read_zip(&archive, 1 << 20, 1 << 20, 4).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn read_zip_rejects_other_files() {
+        assert!(read_zip(b"not a zip archive", 100, 100, 10).is_err());
+    }
+
+    #[test]
+    fn archive_format_from_str() {
+        assert_eq!("zip".parse::<ArchiveFormat>(), Ok(ArchiveFormat::Zip));
+        assert_eq!("TGZ".parse::<ArchiveFormat>(), Ok(ArchiveFormat::TarGz));
+        assert_eq!("tar.gz".parse::<ArchiveFormat>(), Ok(ArchiveFormat::TarGz));
+        assert!("rar".parse::<ArchiveFormat>().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+// Estimates of a task's run time and peak memory, computed before it is submitted
+// Run time follows a work model of the pipeline calibrated against recently completed tasks;
+// memory follows the data structures the pipeline keeps in memory
+use serde::Serialize;
+use crate::kmap_algorithms::kmer_count::KMER_LENGTH;
+use crate::models::{ProcessForm, RuntimeSample};
+
+// Seconds per work unit assumed until completed tasks calibrate it
+const DEFAULT_SECONDS_PER_UNIT: f64 = 2e-8;
+
+// BED uploads hold interval lines, not sequence; intervals are about this long on disk
+// and this wide when no fixed window is set
+const BED_BYTES_PER_INTERVAL: u64 = 50;
+const DEFAULT_BED_INTERVAL_WIDTH: u64 = 500;
+
+// Memory the pipeline needs whatever the input, and per distinct k-mer counted
+const BASE_MEMORY_BYTES: u64 = 64 * 1024 * 1024;
+const BYTES_PER_KMER: u64 = 48;
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Estimate {
+    pub runtime_seconds: u64,
+    pub peak_memory_bytes: u64,
+}
+
+// Amount of sequence a task will process, from the size of its upload
+pub fn sequence_bytes(upload_bytes: u64, input_is_bed: bool, form: &ProcessForm) -> u64 {
+    if input_is_bed {
+        let width = if form.window_width > 0 { form.window_width as u64 } else { DEFAULT_BED_INTERVAL_WIDTH };
+        upload_bytes / BED_BYTES_PER_INTERVAL * width
+    } else {
+        upload_bytes
+    }
+}
+
+// Work model of the pipeline: counting reads the input once, every motif compares its seed
+// against all distinct k-mers, then scans the input on each strand once for site search and
+// once per EM iteration. n_trial is not used by any stage, so it does not add work
+pub fn work_units(sequence_bytes: u64, form: &ProcessForm) -> f64 {
+    let bytes = sequence_bytes as f64;
+    let top_k = form.top_k as f64;
+    let strands = if form.revcom_mode && form.alphabet.supports_revcom() { 2.0 } else { 1.0 };
+    let em_iterations = if form.em_refine { form.em_max_iter as f64 } else { 0.0 };
+    let distinct_kmers = distinct_kmers(sequence_bytes, form) as f64;
+
+    bytes + top_k * (distinct_kmers + bytes * strands * (1.0 + em_iterations))
+}
+
+// Helper function to bound the number of distinct k-mers by the input and the alphabet
+fn distinct_kmers(sequence_bytes: u64, form: &ProcessForm) -> u64 {
+    (form.alphabet.size() as u64)
+        .checked_pow(KMER_LENGTH as u32)
+        .unwrap_or(u64::MAX)
+        .min(sequence_bytes)
+}
+
+// Seconds per work unit over recent tasks; the median keeps outliers from skewing it
+pub fn calibrate(samples: &[RuntimeSample]) -> f64 {
+    let mut rates: Vec<f64> = samples.iter()
+        .filter(|sample| sample.work_units > 0.0)
+        .map(|sample| sample.seconds as f64 / sample.work_units)
+        .collect();
+    if rates.is_empty() {
+        return DEFAULT_SECONDS_PER_UNIT;
+    }
+    rates.sort_by(f64::total_cmp);
+    rates[rates.len() / 2]
+}
+
+pub fn estimate(sequence_bytes: u64, form: &ProcessForm, seconds_per_unit: f64) -> Estimate {
+    let runtime_seconds = (work_units(sequence_bytes, form) * seconds_per_unit).ceil() as u64;
+
+    // Sequences are held once as read and once encoded for EM, next to the k-mer table
+    let peak_memory_bytes = BASE_MEMORY_BYTES
+        + 2 * sequence_bytes
+        + BYTES_PER_KMER * distinct_kmers(sequence_bytes, form);
+
+    Estimate {
+        runtime_seconds: runtime_seconds.max(1),
+        peak_memory_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmap_algorithms::alphabet::Alphabet;
+
+    fn form() -> ProcessForm {
+        ProcessForm { top_k: 2, revcom_mode: true, em_refine: true, em_max_iter: 3, ..Default::default() }
+    }
+
+    #[test]
+    fn sequence_bytes_of_bed_uploads() {
+        assert_eq!(sequence_bytes(5000, false, &form()), 5000);
+        // 100 intervals of the default width
+        assert_eq!(sequence_bytes(5000, true, &form()), 50_000);
+        let window = ProcessForm { window_width: 200, ..form() };
+        assert_eq!(sequence_bytes(5000, true, &window), 20_000);
+    }
+
+    #[test]
+    fn work_units_follow_the_pipeline() {
+        // Counting, then per motif the distinct k-mers and two strands over 1 + 3 passes
+        assert_eq!(work_units(1000, &form()), 1000.0 + 2.0 * (1000.0 + 1000.0 * 2.0 * 4.0));
+
+        let single_pass = ProcessForm { revcom_mode: false, em_refine: false, ..form() };
+        assert_eq!(work_units(1000, &single_pass), 1000.0 + 2.0 * (1000.0 + 1000.0));
+
+        // Protein is never scanned on a second strand
+        let protein = ProcessForm { alphabet: Alphabet::Protein, ..form() };
+        assert_eq!(work_units(1000, &protein), 1000.0 + 2.0 * (1000.0 + 1000.0 * 4.0));
+
+        // Distinct DNA k-mers are bounded by 4^8
+        let large = 1_000_000;
+        assert_eq!(work_units(large, &form()), 1e6 + 2.0 * (65536.0 + 1e6 * 2.0 * 4.0));
+    }
+
+    #[test]
+    fn calibrate_takes_median_rate() {
+        assert_eq!(calibrate(&[]), DEFAULT_SECONDS_PER_UNIT);
+        assert_eq!(calibrate(&[RuntimeSample { work_units: 0.0, seconds: 5 }]), DEFAULT_SECONDS_PER_UNIT);
+
+        let samples = [
+            RuntimeSample { work_units: 100.0, seconds: 1 },
+            RuntimeSample { work_units: 100.0, seconds: 500 },
+            RuntimeSample { work_units: 100.0, seconds: 2 },
+        ];
+        assert_eq!(calibrate(&samples), 0.02);
+    }
+
+    #[test]
+    fn estimate_runtime_and_memory() {
+        let estimate_1k = estimate(1000, &form(), 1e-3);
+        assert_eq!(estimate_1k.runtime_seconds, 19);
+        assert_eq!(estimate_1k.peak_memory_bytes, BASE_MEMORY_BYTES + 2000 + 48 * 1000);
+
+        // Small tasks still take a second
+        assert_eq!(estimate(10, &form(), 1e-9).runtime_seconds, 1);
+    }
+}
@@ -1,4 +1,8 @@
 mod redis_service;
+pub mod result_cache;
+pub mod archive;
+pub mod estimator;
+pub mod storage;
 //mod task_service;
 //mod task_queue;
 
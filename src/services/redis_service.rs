@@ -1,6 +1,154 @@
-use redis::{Client, AsyncCommands};
-use std::sync::Arc;
-use crate::models::{User, TaskInfo};
+use redis::{Client, AsyncCommands, Direction, Script};
+use redis::aio::Connection;
+use std::sync::{Arc, LazyLock};
+use crate::config::RedisConfig;
+use crate::models::{User, QuotaLedgerEntry, BatchInfo, CachedResult, TaskInfo, TaskPriority, TaskProgress, RuntimeSample, WorkerHeartbeat};
+
+// How long an unanswered cancel request is kept, one day
+const CANCEL_FLAG_TTL_SECS: usize = 86400;
+
+// Tasks dispatched by the scheduler, consumed by workers
+const READY_QUEUE_KEY: &str = "task_queue";
+// Users with queued tasks, in the order the scheduler serves them
+const ACTIVE_USERS_KEY: &str = "active_users";
+// Owner of every dispatched but unacknowledged task; running:<user> holds the same ids per user
+const DISPATCHED_KEY: &str = "dispatched";
+const SCHEDULER_WAKEUP_KEY: &str = "scheduler_wakeup";
+// Tasks waiting to be retried, scored by the Unix time they are due
+const DELAYED_KEY: &str = "delayed_tasks";
+// Failed tasks that ran out of retries, newest first
+const DEAD_LETTER_KEY: &str = "dead_letter";
+const TASK_DURATIONS_KEY: &str = "task_durations";
+const TASK_DURATIONS_KEPT: usize = 100;
+// Work and run time of recently completed tasks, newest first, for run time estimates
+const RUNTIME_SAMPLES_KEY: &str = "task_runtime_samples";
+// Held by the janitor run in progress, so that several processes do not clean up at once
+const JANITOR_LOCK_KEY: &str = "janitor_lock";
+
+// Shared by the scheduler scripts below; Redis runs each script atomically
+// The scripts derive running:<user> and task_queue:<user>:<level> from the users they find,
+// so those keys are not declared in KEYS. This needs a single Redis node, standalone or
+// behind Sentinel; Redis Cluster would reject the undeclared keys
+const SCHEDULER_LUA_HELPERS: &str = r#"
+local function release(dispatched_key, task_id)
+    local user = redis.call('HGET', dispatched_key, task_id)
+    if user then
+        redis.call('HDEL', dispatched_key, task_id)
+        redis.call('SREM', 'running:' .. user, task_id)
+    end
+end
+
+local function join_rotation(active_users_key, user)
+    if not redis.call('LPOS', active_users_key, user) then
+        redis.call('RPUSH', active_users_key, user)
+    end
+end
+
+local function wake(wakeup_key)
+    redis.call('LPUSH', wakeup_key, 1)
+    redis.call('LTRIM', wakeup_key, 0, 0)
+end
+"#;
+
+// KEYS: user queue, active users, wakeup; ARGV: task id, user
+const ENQUEUE_SCRIPT: &str = r#"
+redis.call('LPUSH', KEYS[1], ARGV[1])
+join_rotation(KEYS[2], ARGV[2])
+wake(KEYS[3])
+"#;
+
+// KEYS: active users, ready queue, dispatched; ARGV: ready limit, per-user cap
+// Queue levels match TaskPriority::as_str, highest first
+const DISPATCH_SCRIPT: &str = r#"
+if redis.call('LLEN', KEYS[2]) >= tonumber(ARGV[1]) then
+    return false
+end
+local max_running = tonumber(ARGV[2])
+for _ = 1, redis.call('LLEN', KEYS[1]) do
+    local user = redis.call('LMOVE', KEYS[1], KEYS[1], 'LEFT', 'RIGHT')
+    local queues = {}
+    for _, level in ipairs({'high', 'normal', 'low'}) do
+        table.insert(queues, 'task_queue:' .. user .. ':' .. level)
+    end
+    local queued = 0
+    for _, queue in ipairs(queues) do
+        queued = queued + redis.call('LLEN', queue)
+    end
+    if queued == 0 then
+        redis.call('LREM', KEYS[1], 1, user)
+    elseif max_running == 0 or redis.call('SCARD', 'running:' .. user) < max_running then
+        for _, queue in ipairs(queues) do
+            local task_id = redis.call('RPOP', queue)
+            if task_id then
+                redis.call('LPUSH', KEYS[2], task_id)
+                redis.call('HSET', KEYS[3], task_id, user)
+                redis.call('SADD', 'running:' .. user, task_id)
+                return task_id
+            end
+        end
+    end
+end
+return false
+"#;
+
+// KEYS: processing list, dispatched, wakeup; ARGV: task id
+const ACK_SCRIPT: &str = r#"
+redis.call('LREM', KEYS[1], 1, ARGV[1])
+release(KEYS[2], ARGV[1])
+wake(KEYS[3])
+"#;
+
+// KEYS: processing list, dispatched, wakeup, user queue, active users; ARGV: task id, user
+const REQUEUE_SCRIPT: &str = r#"
+if redis.call('LREM', KEYS[1], 1, ARGV[1]) > 0 then
+    redis.call('RPUSH', KEYS[4], ARGV[1])
+    join_rotation(KEYS[5], ARGV[2])
+end
+release(KEYS[2], ARGV[1])
+wake(KEYS[3])
+"#;
+
+// KEYS: processing list, dispatched, wakeup, delayed; ARGV: task id, due time
+const DELAY_SCRIPT: &str = r#"
+redis.call('LREM', KEYS[1], 1, ARGV[1])
+release(KEYS[2], ARGV[1])
+redis.call('ZADD', KEYS[4], ARGV[2], ARGV[1])
+wake(KEYS[3])
+"#;
+
+// KEYS: delayed, user queue, active users, wakeup; ARGV: task id, user
+const PROMOTE_SCRIPT: &str = r#"
+if redis.call('ZREM', KEYS[1], ARGV[1]) > 0 then
+    redis.call('LPUSH', KEYS[2], ARGV[1])
+    join_rotation(KEYS[3], ARGV[2])
+    wake(KEYS[4])
+end
+"#;
+
+// KEYS: user queue, ready queue, dispatched, wakeup, delayed; ARGV: task id
+const REMOVE_QUEUED_SCRIPT: &str = r#"
+if redis.call('LREM', KEYS[1], 0, ARGV[1]) > 0 then
+    return 1
+end
+if redis.call('ZREM', KEYS[5], ARGV[1]) > 0 then
+    return 1
+end
+if redis.call('LREM', KEYS[2], 0, ARGV[1]) > 0 then
+    release(KEYS[3], ARGV[1])
+    wake(KEYS[4])
+    return 1
+end
+return 0
+"#;
+
+// Built once; each invocation sends EVALSHA and loads the script only if Redis lacks it
+static ENQUEUE: LazyLock<Script> = LazyLock::new(|| scheduler_script(ENQUEUE_SCRIPT));
+static DISPATCH: LazyLock<Script> = LazyLock::new(|| scheduler_script(DISPATCH_SCRIPT));
+static ACK: LazyLock<Script> = LazyLock::new(|| scheduler_script(ACK_SCRIPT));
+static REQUEUE: LazyLock<Script> = LazyLock::new(|| scheduler_script(REQUEUE_SCRIPT));
+static DELAY: LazyLock<Script> = LazyLock::new(|| scheduler_script(DELAY_SCRIPT));
+static PROMOTE: LazyLock<Script> = LazyLock::new(|| scheduler_script(PROMOTE_SCRIPT));
+static REMOVE_QUEUED: LazyLock<Script> = LazyLock::new(|| scheduler_script(REMOVE_QUEUED_SCRIPT));
 
 pub struct RedisService {
     client: Arc<Client>,
@@ -11,6 +159,18 @@ impl RedisService {
         Self { client }
     }
 
+    // Connect directly or through Sentinel, as configured
+    pub fn from_config(config: &RedisConfig) -> Result<Self, redis::RedisError> {
+        let url = if config.sentinel_enabled {
+            config.sentinel_url.as_deref().ok_or_else(|| {
+                redis::RedisError::from((redis::ErrorKind::InvalidClientConfig, "Sentinel URL not configured"))
+            })?
+        } else {
+            config.url.as_str()
+        };
+        Ok(Self::new(Arc::new(Client::open(url)?)))
+    }
+
     pub async fn get_user(&self, username: &str) -> Result<Option<User>, redis::RedisError> {
         let mut conn = self.client.get_async_connection().await?;
         let user_data: Option<String> = conn.get(format!("user:{}", username)).await?;
@@ -25,6 +185,64 @@ impl RedisService {
         ).await
     }
 
+    pub async fn get_all_usernames(&self) -> Result<Vec<String>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let mut keys = conn.scan_match::<_, String>("user:*").await?;
+        let mut usernames = Vec::new();
+        while let Some(key) = keys.next_item().await {
+            if let Some(username) = key.strip_prefix("user:") {
+                usernames.push(username.to_string());
+            }
+        }
+        Ok(usernames)
+    }
+
+    // Read-modify-write a user under WATCH, retrying whenever another client changed it meanwhile
+    // Returns the closure's output, or None if the user does not exist
+    pub async fn update_user<T, F>(&self, username: &str, mut update: F) -> Result<Option<T>, redis::RedisError>
+    where
+        F: FnMut(&mut User) -> T,
+    {
+        let mut conn = self.client.get_async_connection().await?;
+        let user_key = format!("user:{}", username);
+        loop {
+            redis::cmd("WATCH").arg(&user_key).query_async::<_, ()>(&mut conn).await?;
+            let user_data: Option<String> = conn.get(&user_key).await?;
+            let Some(user_data) = user_data else {
+                redis::cmd("UNWATCH").query_async::<_, ()>(&mut conn).await?;
+                return Ok(None);
+            };
+
+            let mut user: User = serde_json::from_str(&user_data).unwrap();
+            let output = update(&mut user);
+
+            // EXEC returns nil when the watched key changed before it ran
+            let committed: Option<()> = redis::pipe()
+                .atomic()
+                .set(&user_key, serde_json::to_string(&user).unwrap())
+                .ignore()
+                .query_async(&mut conn)
+                .await?;
+            if committed.is_some() {
+                return Ok(Some(output));
+            }
+            tracing::debug!("User {} changed concurrently, retrying update", username);
+        }
+    }
+
+    // Newest entries first
+    pub async fn append_quota_ledger(
+        &self,
+        username: &str,
+        entry: &QuotaLedgerEntry,
+    ) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.lpush(
+            format!("quota_ledger:{}", username),
+            serde_json::to_string(entry).unwrap()
+        ).await
+    }
+
     pub async fn get_task(&self, task_id: &str) -> Result<Option<TaskInfo>, redis::RedisError> {
         let mut conn = self.client.get_async_connection().await?;
         let task_data: Option<String> = conn.get(format!("task:{}", task_id)).await?;
@@ -39,32 +257,475 @@ impl RedisService {
         ).await
     }
 
+    pub async fn get_progress(&self, task_id: &str) -> Result<Option<TaskProgress>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let progress_data: Option<String> = conn.get(progress_key(task_id)).await?;
+        Ok(progress_data.map(|data| serde_json::from_str(&data).unwrap()))
+    }
+
+    pub async fn get_batch(&self, batch_id: &str) -> Result<Option<BatchInfo>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let batch_data: Option<String> = conn.get(format!("batch:{}", batch_id)).await?;
+        Ok(batch_data.map(|data| serde_json::from_str(&data).unwrap()))
+    }
+
+    pub async fn save_batch(&self, batch: &BatchInfo) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.set(
+            format!("batch:{}", batch.batch_id),
+            serde_json::to_string(batch).unwrap()
+        ).await
+    }
+
+    pub async fn save_progress(&self, task_id: &str, progress: &TaskProgress) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.set(
+            progress_key(task_id),
+            serde_json::to_string(progress).unwrap()
+        ).await
+    }
+
+    // Queued tasks wait in per-user queues, one per priority; producers push on the left and
+    // the scheduler takes from the right. The user joins the scheduler's rotation if needed
     pub async fn queue_task(&self, task: &TaskInfo) -> Result<(), redis::RedisError> {
         let mut conn = self.client.get_async_connection().await?;
-        conn.lpush("task_queue", serde_json::to_string(task).unwrap()).await
+        ENQUEUE
+            .key(user_queue_key(&task.user, task.priority))
+            .key(ACTIVE_USERS_KEY)
+            .key(SCHEDULER_WAKEUP_KEY)
+            .arg(&task.task_id)
+            .arg(&task.user)
+            .invoke_async(&mut conn)
+            .await
     }
 
-    pub async fn pop_task(&self) -> Result<Option<TaskInfo>, redis::RedisError> {
+    // Move the next task, taking users in turn, into the ready queue that workers consume
+    // Users at their concurrency cap are skipped (max_running 0 means no cap) and users with
+    // nothing queued leave the rotation. Returns None when nothing could be dispatched
+    pub async fn dispatch_next_task(
+        &self,
+        ready_limit: usize,
+        max_running: usize,
+    ) -> Result<Option<String>, redis::RedisError> {
         let mut conn = self.client.get_async_connection().await?;
-        
-        // Try to pop a task from the queue
-        if let Some(task_json) = conn.rpop::<_, Option<String>>("task_queue", None).await? {
-            // Parse the JSON into TaskInfo
-            let task = serde_json::from_str(&task_json)
-                .map_err(|e| redis::RedisError::from((redis::ErrorKind::TypeError, "Failed to parse task", e.to_string())))?;
-            Ok(Some(task))
-        } else {
-            Ok(None)
+        DISPATCH
+            .key(ACTIVE_USERS_KEY)
+            .key(READY_QUEUE_KEY)
+            .key(DISPATCHED_KEY)
+            .arg(ready_limit)
+            .arg(max_running)
+            .invoke_async(&mut conn)
+            .await
+    }
+
+    // Wait on a dedicated connection until something may have become dispatchable
+    pub async fn wait_for_scheduler_wakeup(
+        &self,
+        conn: &mut Connection,
+        timeout_secs: f64,
+    ) -> Result<(), redis::RedisError> {
+        let _: Option<(String, String)> = conn.blpop(SCHEDULER_WAKEUP_KEY, timeout_secs).await?;
+        Ok(())
+    }
+
+    // Number of tasks that will be dispatched before this one, None if it is not queued
+    // Users are served in turn, so each other user gets at most one task ahead per task of ours
+    pub async fn queue_position(&self, task: &TaskInfo) -> Result<Option<u64>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+
+        // Already dispatched: only the ready tasks to its right are ahead
+        let ready_len: u64 = conn.llen(READY_QUEUE_KEY).await?;
+        let ready_index: Option<u64> = redis::cmd("LPOS")
+            .arg(READY_QUEUE_KEY)
+            .arg(&task.task_id)
+            .query_async(&mut conn)
+            .await?;
+        if let Some(index) = ready_index {
+            return Ok(Some(ready_len - 1 - index));
+        }
+
+        // Otherwise count our own tasks ahead: higher priorities first, then older tasks
+        let mut own_ahead = 0u64;
+        let mut found = false;
+        for priority in TaskPriority::ALL {
+            let queue_key = user_queue_key(&task.user, priority);
+            let queue_len: u64 = conn.llen(&queue_key).await?;
+            if priority == task.priority {
+                let index: Option<u64> = redis::cmd("LPOS")
+                    .arg(&queue_key)
+                    .arg(&task.task_id)
+                    .query_async(&mut conn)
+                    .await?;
+                if let Some(index) = index {
+                    own_ahead += queue_len - 1 - index;
+                    found = true;
+                }
+                break;
+            }
+            own_ahead += queue_len;
+        }
+        if !found {
+            return Ok(None);
+        }
+
+        let active_users: Vec<String> = conn.lrange(ACTIVE_USERS_KEY, 0, -1).await?;
+        let mut others_ahead = 0u64;
+        for user in active_users.iter().filter(|user| **user != task.user) {
+            let mut queued = 0u64;
+            for priority in TaskPriority::ALL {
+                let queue_len: u64 = conn.llen(user_queue_key(user, priority)).await?;
+                queued += queue_len;
+            }
+            others_ahead += queued.min(own_ahead + 1);
+        }
+
+        Ok(Some(ready_len + own_ahead + others_ahead))
+    }
+
+    // Remember how long completed tasks ran, keeping the most recent ones
+    pub async fn record_task_duration(&self, seconds: u64) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        redis::pipe()
+            .lpush(TASK_DURATIONS_KEY, seconds)
+            .ignore()
+            .ltrim(TASK_DURATIONS_KEY, 0, TASK_DURATIONS_KEPT as isize - 1)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+    }
+
+    // Mean run time of recently completed tasks, None without history
+    pub async fn average_task_duration(&self) -> Result<Option<f64>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let durations: Vec<u64> = conn.lrange(TASK_DURATIONS_KEY, 0, -1).await?;
+        if durations.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(durations.iter().sum::<u64>() as f64 / durations.len() as f64))
+    }
+
+    pub async fn record_runtime_sample(&self, sample: &RuntimeSample) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        redis::pipe()
+            .lpush(RUNTIME_SAMPLES_KEY, serde_json::to_string(sample).unwrap())
+            .ignore()
+            .ltrim(RUNTIME_SAMPLES_KEY, 0, TASK_DURATIONS_KEPT as isize - 1)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+    }
+
+    pub async fn get_runtime_samples(&self) -> Result<Vec<RuntimeSample>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let samples: Vec<String> = conn.lrange(RUNTIME_SAMPLES_KEY, 0, -1).await?;
+        Ok(samples.iter().map(|data| serde_json::from_str(data).unwrap()).collect())
+    }
+
+    // Open a connection owned by a single consumer, so blocking pops never hold up other commands
+    pub async fn dedicated_connection(&self) -> Result<Connection, redis::RedisError> {
+        self.client.get_async_connection().await
+    }
+
+    // Reliable dequeue: atomically move the next task id into the worker's processing list,
+    // where it stays until ack_task so that a crashed worker's task can be recovered.
+    // Blocks the given connection for up to timeout_secs when the queue is empty
+    pub async fn dequeue_task(
+        &self,
+        conn: &mut Connection,
+        worker_id: &str,
+        timeout_secs: f64,
+    ) -> Result<Option<String>, redis::RedisError> {
+        let task_id: Option<String> = conn.blmove(
+            READY_QUEUE_KEY,
+            processing_key(worker_id),
+            Direction::Right,
+            Direction::Left,
+            timeout_secs,
+        ).await?;
+
+        // The ready queue has room again
+        if task_id.is_some() {
+            wake_scheduler(conn).await?;
+        }
+        Ok(task_id)
+    }
+
+    // Acknowledge a finished task by dropping it from the worker's processing list,
+    // which also frees its slot under the user's concurrency cap
+    pub async fn ack_task(&self, worker_id: &str, task_id: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        ACK
+            .key(processing_key(worker_id))
+            .key(DISPATCHED_KEY)
+            .key(SCHEDULER_WAKEUP_KEY)
+            .arg(task_id)
+            .invoke_async(&mut conn)
+            .await
+    }
+
+    // Record a worker id so that its processing list can be found after a crash
+    pub async fn register_worker(&self, worker_id: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.sadd("workers", worker_id).await
+    }
+
+    pub async fn get_workers(&self) -> Result<Vec<String>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.smembers("workers").await
+    }
+
+    pub async fn unregister_worker(&self, worker_id: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.srem("workers", worker_id).await
+    }
+
+    // Refresh a worker's heartbeat; the key disappears unless refreshed within ttl_secs
+    pub async fn set_heartbeat(
+        &self,
+        heartbeat: &WorkerHeartbeat,
+        ttl_secs: u64,
+    ) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.set_ex(
+            heartbeat_key(&heartbeat.worker_id),
+            serde_json::to_string(heartbeat).unwrap(),
+            ttl_secs as usize,
+        ).await
+    }
+
+    pub async fn get_heartbeat(&self, worker_id: &str) -> Result<Option<WorkerHeartbeat>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let heartbeat_data: Option<String> = conn.get(heartbeat_key(worker_id)).await?;
+        Ok(heartbeat_data.map(|data| serde_json::from_str(&data).unwrap()))
+    }
+
+    // Task ids a worker has taken but not acknowledged yet
+    pub async fn get_worker_tasks(&self, worker_id: &str) -> Result<Vec<String>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.lrange(processing_key(worker_id), 0, -1).await
+    }
+
+    // Atomically move a task from a worker's processing list back to the front of its user's queue
+    pub async fn requeue_worker_task(&self, worker_id: &str, task: &TaskInfo) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        REQUEUE
+            .key(processing_key(worker_id))
+            .key(DISPATCHED_KEY)
+            .key(SCHEDULER_WAKEUP_KEY)
+            .key(user_queue_key(&task.user, task.priority))
+            .key(ACTIVE_USERS_KEY)
+            .arg(&task.task_id)
+            .arg(&task.user)
+            .invoke_async(&mut conn)
+            .await
+    }
+
+    // Atomically move a failed task from a worker's processing list to the delayed set,
+    // to be queued again at due_timestamp
+    pub async fn delay_task(
+        &self,
+        worker_id: &str,
+        task_id: &str,
+        due_timestamp: i64,
+    ) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        DELAY
+            .key(processing_key(worker_id))
+            .key(DISPATCHED_KEY)
+            .key(SCHEDULER_WAKEUP_KEY)
+            .key(DELAYED_KEY)
+            .arg(task_id)
+            .arg(due_timestamp)
+            .invoke_async(&mut conn)
+            .await
+    }
+
+    // Delayed task ids due at or before now_timestamp, oldest first
+    pub async fn get_due_tasks(&self, now_timestamp: i64) -> Result<Vec<String>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.zrangebyscore(DELAYED_KEY, "-inf", now_timestamp).await
+    }
+
+    // Queue a due delayed task again; a no-op if another scheduler got to it first
+    pub async fn promote_delayed_task(&self, task: &TaskInfo) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        PROMOTE
+            .key(DELAYED_KEY)
+            .key(user_queue_key(&task.user, task.priority))
+            .key(ACTIVE_USERS_KEY)
+            .key(SCHEDULER_WAKEUP_KEY)
+            .arg(&task.task_id)
+            .arg(&task.user)
+            .invoke_async(&mut conn)
+            .await
+    }
+
+    pub async fn add_dead_letter_task(&self, task_id: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.lpush(DEAD_LETTER_KEY, task_id).await
+    }
+
+    pub async fn get_dead_letter_tasks(&self) -> Result<Vec<String>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.lrange(DEAD_LETTER_KEY, 0, -1).await
+    }
+
+    // Returns false if the task was not dead-lettered
+    pub async fn remove_dead_letter_task(&self, task_id: &str) -> Result<bool, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let removed: usize = conn.lrem(DEAD_LETTER_KEY, 0, task_id).await?;
+        Ok(removed > 0)
+    }
+
+    // Take a task out of its user's queue, the retry schedule or the ready queue,
+    // before any worker picked it up
+    // Returns false if it was no longer queued
+    pub async fn remove_queued_task(&self, task: &TaskInfo) -> Result<bool, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let removed: u32 = REMOVE_QUEUED
+            .key(user_queue_key(&task.user, task.priority))
+            .key(READY_QUEUE_KEY)
+            .key(DISPATCHED_KEY)
+            .key(SCHEDULER_WAKEUP_KEY)
+            .key(DELAYED_KEY)
+            .arg(&task.task_id)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(removed > 0)
+    }
+
+    // Ask the worker running a task to stop; the flag expires in case nobody picks it up
+    pub async fn request_cancel(&self, task_id: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.set_ex(cancel_key(task_id), 1, CANCEL_FLAG_TTL_SECS).await
+    }
+
+    pub async fn is_cancel_requested(&self, task_id: &str) -> Result<bool, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.exists(cancel_key(task_id)).await
+    }
+
+    pub async fn clear_cancel_request(&self, task_id: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.del(cancel_key(task_id)).await
+    }
+
+    // Mark a running task for removal once its worker has recorded how it ended
+    // Unlike a cancel request the mark does not expire; it goes with the task
+    pub async fn mark_deleted(&self, task_id: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.set(deleted_key(task_id), 1).await
+    }
+
+    pub async fn is_marked_deleted(&self, task_id: &str) -> Result<bool, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.exists(deleted_key(task_id)).await
+    }
+
+    pub async fn get_cached_result(&self, cache_key: &str) -> Result<Option<CachedResult>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let entry: Option<String> = conn.get(result_cache_key(cache_key)).await?;
+        Ok(entry.map(|data| serde_json::from_str(&data).unwrap()))
+    }
+
+    // Entries expire with the results they point at, see retention.result_ttl
+    pub async fn set_cached_result(
+        &self,
+        cache_key: &str,
+        entry: &CachedResult,
+        ttl_secs: u64,
+    ) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.set_ex(
+            result_cache_key(cache_key),
+            serde_json::to_string(entry).unwrap(),
+            ttl_secs as usize
+        ).await
+    }
+
+    pub async fn remove_cached_result(&self, cache_key: &str) -> Result<(), redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        conn.del(result_cache_key(cache_key)).await
+    }
+
+    // Ids of all tasks stored in Redis, including those no user lists anymore
+    pub async fn get_all_task_ids(&self) -> Result<Vec<String>, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let mut keys = conn.scan_match::<_, String>("task:*").await?;
+        let mut task_ids = Vec::new();
+        while let Some(key) = keys.next_item().await {
+            if let Some(task_id) = key.strip_prefix("task:") {
+                task_ids.push(task_id.to_string());
+            }
         }
+        Ok(task_ids)
+    }
+
+    // Returns false if another process holds the lock
+    // The lock is not released but expires, which also spaces out runs of several processes
+    pub async fn try_acquire_janitor_lock(&self, ttl_secs: u64) -> Result<bool, redis::RedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(JANITOR_LOCK_KEY)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_secs)
+            .query_async(&mut conn)
+            .await?;
+        Ok(acquired.is_some())
     }
 
     pub async fn delete_task(&self, task_id: &str) -> Result<(), redis::RedisError> {
         let mut conn = self.client.get_async_connection().await?;
         let task_key = format!("task:{}", task_id);
-        conn.del(&task_key).await
+        conn.del(&[task_key, progress_key(task_id), deleted_key(task_id)]).await
     }
 }
 
+fn user_queue_key(username: &str, priority: TaskPriority) -> String {
+    format!("task_queue:{}:{}", username, priority.as_str())
+}
+
+// Nudge the scheduler; the wakeup list never holds more than one entry
+async fn wake_scheduler(conn: &mut Connection) -> Result<(), redis::RedisError> {
+    redis::pipe()
+        .lpush(SCHEDULER_WAKEUP_KEY, 1)
+        .ignore()
+        .ltrim(SCHEDULER_WAKEUP_KEY, 0, 0)
+        .ignore()
+        .query_async(conn)
+        .await
+}
+
+fn scheduler_script(body: &str) -> Script {
+    Script::new(&format!("{}\n{}", SCHEDULER_LUA_HELPERS, body))
+}
+
+fn processing_key(worker_id: &str) -> String {
+    format!("processing:{}", worker_id)
+}
+
+fn progress_key(task_id: &str) -> String {
+    format!("progress:{}", task_id)
+}
+
+fn cancel_key(task_id: &str) -> String {
+    format!("cancel:{}", task_id)
+}
+
+fn deleted_key(task_id: &str) -> String {
+    format!("deleted:{}", task_id)
+}
+
+fn result_cache_key(cache_key: &str) -> String {
+    format!("result_cache:{}", cache_key)
+}
+
+fn heartbeat_key(worker_id: &str) -> String {
+    format!("heartbeat:{}", worker_id)
+}
+
 impl Clone for RedisService {
     fn clone(&self) -> Self {
         Self {
@@ -0,0 +1,151 @@
+// Result cache: submissions of an input already processed with the same parameters
+// reuse the earlier results instead of running again
+use std::fs;
+use std::io;
+use std::path::Path;
+use sha2::{Digest, Sha256};
+use crate::config::GenomeConfig;
+use crate::models::ProcessForm;
+use crate::worker::CHECKPOINT_DIR;
+
+// Cache key from the input's SHA-256, the parameters that affect the results and the
+// software version, so an upgrade never serves results of an older pipeline
+// BED inputs also key on the reference genome their sequences are extracted from
+pub fn cache_key(input_sha256: &str, params: &ProcessForm, reference: Option<&GenomeConfig>) -> String {
+    let mut normalized = params.clone();
+    // No stage draws random numbers yet, so the seed does not change the results
+    // A stage that does must take its RNG from the seed, and the seed must then stay in the key
+    normalized.seed = None;
+    if !normalized.em_refine {
+        normalized.em_max_iter = 0;
+    }
+    if !normalized.alphabet.supports_revcom() {
+        normalized.revcom_mode = false;
+    }
+    if reference.is_none() {
+        normalized.genome = None;
+        normalized.recenter = false;
+        normalized.window_width = 0;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    match reference {
+        Some(genome) => {
+            hasher.update("\nbed\n");
+            hasher.update(&genome.name);
+            hasher.update("\n");
+            hasher.update(&genome.fasta_path);
+            hasher.update("\n");
+        }
+        None => hasher.update("\nfasta\n"),
+    }
+    hasher.update(input_sha256);
+    hasher.update("\n");
+    hasher.update(serde_json::to_string(&normalized).unwrap());
+    format!("{:x}", hasher.finalize())
+}
+
+// Reproduce a result directory by hard-linking its files, copying where linking is not possible
+// Linked results outlive the deletion of the directory they came from
+// Checkpoints only serve to resume the run that wrote them, so they are left out
+pub fn link_results(source: &Path, destination: &Path) -> io::Result<()> {
+    fs::create_dir_all(destination)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target = destination.join(entry.file_name());
+        if entry.file_name() == CHECKPOINT_DIR {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            link_results(&entry.path(), &target)?;
+        } else if fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmap_algorithms::alphabet::Alphabet;
+
+    const INPUT: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn form() -> ProcessForm {
+        ProcessForm { n_trial: 10, top_k: 5, em_refine: true, em_max_iter: 20, ..Default::default() }
+    }
+
+    fn genome(name: &str, fasta_path: &str) -> GenomeConfig {
+        GenomeConfig { name: name.into(), fasta_path: fasta_path.into() }
+    }
+
+    #[test]
+    fn cache_key_ignores_seed() {
+        let seeded = ProcessForm { seed: Some(42), ..form() };
+        let other_seed = ProcessForm { seed: Some(7), ..form() };
+        assert_eq!(cache_key(INPUT, &seeded, None), cache_key(INPUT, &form(), None));
+        assert_eq!(cache_key(INPUT, &seeded, None), cache_key(INPUT, &other_seed, None));
+    }
+
+    #[test]
+    fn cache_key_ignores_unused_parameters() {
+        // EM iterations only matter when EM runs
+        let no_em = ProcessForm { em_refine: false, ..form() };
+        let no_em_fewer = ProcessForm { em_refine: false, em_max_iter: 3, ..form() };
+        assert_eq!(cache_key(INPUT, &no_em, None), cache_key(INPUT, &no_em_fewer, None));
+        let fewer = ProcessForm { em_max_iter: 3, ..form() };
+        assert_ne!(cache_key(INPUT, &form(), None), cache_key(INPUT, &fewer, None));
+
+        // Protein has no reverse complement
+        let protein = ProcessForm { alphabet: Alphabet::Protein, ..form() };
+        let protein_revcom = ProcessForm { revcom_mode: true, ..protein.clone() };
+        assert_eq!(cache_key(INPUT, &protein, None), cache_key(INPUT, &protein_revcom, None));
+        let dna_revcom = ProcessForm { revcom_mode: true, ..form() };
+        assert_ne!(cache_key(INPUT, &form(), None), cache_key(INPUT, &dna_revcom, None));
+    }
+
+    #[test]
+    fn cache_key_uses_window_only_for_bed() {
+        let hg38 = genome("hg38", "/refs/hg38.fa");
+        let window = ProcessForm {
+            genome: Some("hg38".into()),
+            recenter: true,
+            window_width: 200,
+            ..form()
+        };
+        assert_eq!(cache_key(INPUT, &window, None), cache_key(INPUT, &form(), None));
+        assert_ne!(cache_key(INPUT, &window, Some(&hg38)), cache_key(INPUT, &form(), Some(&hg38)));
+        // The same bytes as BED and as FASTA are different inputs
+        assert_ne!(cache_key(INPUT, &form(), Some(&hg38)), cache_key(INPUT, &form(), None));
+        assert_ne!(cache_key(INPUT, &form(), None), cache_key(&INPUT[1..], &form(), None));
+    }
+
+    #[test]
+    fn cache_key_covers_the_reference_genome() {
+        let params = ProcessForm { genome: Some("hg38".into()), ..form() };
+        let hg38 = cache_key(INPUT, &params, Some(&genome("hg38", "/refs/hg38.fa")));
+        assert_eq!(hg38, cache_key(INPUT, &params, Some(&genome("hg38", "/refs/hg38.fa"))));
+        // A genome name pointing at another reference gives other sequences
+        assert_ne!(hg38, cache_key(INPUT, &params, Some(&genome("hg38", "/refs/mm10.fa"))));
+        assert_ne!(hg38, cache_key(INPUT, &params, Some(&genome("mm10", "/refs/hg38.fa"))));
+    }
+
+    #[test]
+    fn link_results_leaves_out_checkpoints() {
+        let dir = std::env::temp_dir().join(format!("kmap-cache-test-{}", std::process::id()));
+        let (source, destination) = (dir.join("source"), dir.join("destination"));
+        fs::create_dir_all(source.join(CHECKPOINT_DIR)).unwrap();
+        fs::create_dir_all(source.join("logos")).unwrap();
+        fs::write(source.join("results.json"), "{}").unwrap();
+        fs::write(source.join("logos/m1.svg"), "<svg/>").unwrap();
+        fs::write(source.join(CHECKPOINT_DIR).join("stages.json"), "[]").unwrap();
+
+        link_results(&source, &destination).unwrap();
+        assert_eq!(fs::read_to_string(destination.join("results.json")).unwrap(), "{}");
+        assert_eq!(fs::read_to_string(destination.join("logos/m1.svg")).unwrap(), "<svg/>");
+        assert!(!destination.join(CHECKPOINT_DIR).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
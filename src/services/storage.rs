@@ -0,0 +1,18 @@
+// Disk usage of result directories, charged to their users' storage quota
+use std::fs;
+use std::path::Path;
+
+// Add up the sizes of all files under a directory; unreadable entries count as empty
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
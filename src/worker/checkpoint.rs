@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use crate::models::{PipelineStage, StageInfo};
+use crate::errors::worker::{WorkerError, WorkerResult};
+
+// Intermediate artifacts that are not part of the results live in this subdirectory
+pub const CHECKPOINT_DIR: &str = "checkpoints";
+const CHECKPOINT_FILE: &str = "checkpoints/stages.json";
+
+pub const INTERVALS_ARTIFACT: &str = "checkpoints/intervals.json";
+pub const KMER_COUNTS_ARTIFACT: &str = "checkpoints/kmer_counts.json";
+pub const MOTIFS_ARTIFACT: &str = "checkpoints/motifs.json";
+
+// Files a stage must have written for it to count as completed
+// Cluster plots are left out as they are only drawn for two or more motifs
+fn stage_artifacts(stage: PipelineStage) -> &'static [&'static str] {
+    match stage {
+        PipelineStage::ExtractSequences => &["extracted_sequences.fa", INTERVALS_ARTIFACT],
+        PipelineStage::CountKmers => &["top10kmers.txt", KMER_COUNTS_ARTIFACT],
+        PipelineStage::DiscoverMotifs => &[MOTIFS_ARTIFACT],
+        PipelineStage::ClusterMotifs => &["results.json", "motifs.meme"],
+        PipelineStage::ScanSites => &["motif_sites.tsv"],
+    }
+}
+
+// Stage graph of a run; BED uploads start by extracting their sequences from the reference
+pub fn stage_graph(bed_input: bool) -> Vec<StageInfo> {
+    let mut stages = Vec::new();
+    let mut previous = None;
+    let pipeline = [
+        PipelineStage::ExtractSequences,
+        PipelineStage::CountKmers,
+        PipelineStage::DiscoverMotifs,
+        PipelineStage::ClusterMotifs,
+        PipelineStage::ScanSites,
+    ];
+
+    for stage in pipeline {
+        if stage == PipelineStage::ExtractSequences && !bed_input {
+            continue;
+        }
+        stages.push(StageInfo {
+            stage,
+            depends_on: previous.into_iter().collect(),
+            artifacts: stage_artifacts(stage).iter().map(|name| name.to_string()).collect(),
+        });
+        previous = Some(stage);
+    }
+    stages
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CheckpointRecord {
+    completed: Vec<PipelineStage>,
+}
+
+// Tracks which stages of a run finished, in checkpoints/stages.json of the result directory
+pub struct Checkpoint {
+    result_path: PathBuf,
+    record: CheckpointRecord,
+}
+
+impl Checkpoint {
+    // Read the stages completed by earlier attempts; an unreadable record starts over
+    pub fn load(result_path: &Path) -> WorkerResult<Self> {
+        let checkpoint_dir = result_path.join(CHECKPOINT_DIR);
+        std::fs::create_dir_all(&checkpoint_dir)
+            .map_err(|e| {
+                tracing::error!("Failed to create checkpoint directory {}: {}", checkpoint_dir.display(), e);
+                WorkerError::Io(e)
+            })?;
+
+        let record_path = result_path.join(CHECKPOINT_FILE);
+        let record = match std::fs::read(&record_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring unreadable checkpoint {}: {}", record_path.display(), e);
+                CheckpointRecord::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => CheckpointRecord::default(),
+            Err(e) => {
+                tracing::error!("Failed to read checkpoint {}: {}", record_path.display(), e);
+                return Err(WorkerError::Io(e));
+            }
+        };
+
+        Ok(Checkpoint {
+            result_path: result_path.to_path_buf(),
+            record,
+        })
+    }
+
+    // A stage is skipped only if it was recorded as completed and its artifacts are still there
+    pub fn is_complete(&self, stage: PipelineStage) -> bool {
+        let complete = self.record.completed.contains(&stage)
+            && stage_artifacts(stage).iter().all(|name| self.result_path.join(name).exists());
+        if complete {
+            tracing::info!("Skipping stage {} completed by an earlier attempt", stage.as_str());
+        }
+        complete
+    }
+
+    // Record a stage once all its artifacts are written
+    // The record is replaced atomically so a crash never leaves it half written
+    pub fn mark_complete(&mut self, stage: PipelineStage) -> WorkerResult<()> {
+        if !self.record.completed.contains(&stage) {
+            self.record.completed.push(stage);
+        }
+
+        let record_path = self.result_path.join(CHECKPOINT_FILE);
+        let temp_path = record_path.with_extension("json.tmp");
+        save_json(&temp_path, &self.record)?;
+        std::fs::rename(&temp_path, &record_path)
+            .map_err(|e| {
+                tracing::error!("Failed to save checkpoint {}: {}", record_path.display(), e);
+                WorkerError::Io(e)
+            })?;
+
+        tracing::debug!("Checkpointed stage {}", stage.as_str());
+        Ok(())
+    }
+
+    // Helper function to write an intermediate artifact
+    pub fn save_artifact<T: Serialize>(&self, name: &str, value: &T) -> WorkerResult<()> {
+        save_json(&self.result_path.join(name), value)
+    }
+
+    // Helper function to read an intermediate artifact of a completed stage
+    pub fn load_artifact<T: DeserializeOwned>(&self, name: &str) -> WorkerResult<T> {
+        let path = self.result_path.join(name);
+        let file = File::open(&path)
+            .map_err(|e| {
+                tracing::error!("Failed to open artifact {}: {}", path.display(), e);
+                WorkerError::Io(e)
+            })?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| {
+                tracing::error!("Failed to read artifact {}: {}", path.display(), e);
+                WorkerError::Processing(format!("Corrupt checkpoint artifact {}: {}", name, e))
+            })
+    }
+}
+
+// Helper function to serialize a value to a JSON file
+fn save_json<T: Serialize>(path: &Path, value: &T) -> WorkerResult<()> {
+    let file = File::create(path)
+        .map_err(|e| {
+            tracing::error!("Failed to create file {}: {}", path.display(), e);
+            WorkerError::Io(e)
+        })?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, value)
+        .map_err(|e| {
+            tracing::error!("Failed to write {}: {}", path.display(), e);
+            WorkerError::Processing(format!("Failed to write {}: {}", path.display(), e))
+        })?;
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tokio::time::{sleep, Duration};
+use chrono::Utc;
+use crate::models::{TaskInfo, TaskStatus};
+use crate::services::RedisService;
+use crate::config::{RetentionConfig, UploadConfig};
+
+// Uploads, downloads and result directories younger than this may belong to a request still in flight
+const LEFTOVER_GRACE_SECS: u64 = 3600;
+
+// Periodically delete expired results and files no task needs anymore
+// Only one process cleans up at a time; the others skip their turn while it holds the lock
+pub async fn janitor_process(redis_service: RedisService, retention: RetentionConfig, upload: UploadConfig) {
+    tracing::info!("Janitor started");
+
+    loop {
+        match redis_service.try_acquire_janitor_lock(retention.janitor_interval).await {
+            Ok(true) => {
+                if let Err(e) = clean_up(&redis_service, &retention, &upload).await {
+                    tracing::error!("Janitor run failed: {}", e);
+                }
+            }
+            Ok(false) => tracing::debug!("Another process is cleaning up"),
+            Err(e) => tracing::error!("Failed to acquire janitor lock: {}", e),
+        }
+        sleep(Duration::from_secs(retention.janitor_interval)).await;
+    }
+}
+
+async fn clean_up(
+    redis_service: &RedisService,
+    retention: &RetentionConfig,
+    upload: &UploadConfig,
+) -> Result<(), redis::RedisError> {
+    let mut tasks = Vec::new();
+    for task_id in redis_service.get_all_task_ids().await? {
+        if let Some(task) = redis_service.get_task(&task_id).await? {
+            tasks.push(task);
+        }
+    }
+
+    let now = Utc::now();
+    let (expired, kept): (Vec<TaskInfo>, Vec<TaskInfo>) = tasks
+        .into_iter()
+        .partition(|task| task.expires_at(retention.result_ttl).is_some_and(|expires_at| expires_at <= now));
+
+    for task in &expired {
+        tracing::info!("Task {} of user {} expired", task.task_id, task.user);
+        remove_task(redis_service, task).await;
+    }
+
+    // Inputs are needed until a task finishes, and by dead-lettered tasks that may be re-queued
+    let dead_letter: HashSet<String> = redis_service.get_dead_letter_tasks().await?.into_iter().collect();
+    let inputs: HashSet<PathBuf> = kept
+        .iter()
+        .filter(|task| {
+            matches!(task.status, TaskStatus::Queued | TaskStatus::Processing)
+                || dead_letter.contains(&task.task_id)
+        })
+        .map(|task| PathBuf::from(&task.fasta_path))
+        .collect();
+    let result_paths: HashSet<PathBuf> = kept
+        .iter()
+        .map(|task| PathBuf::from(&task.result_path))
+        .collect();
+
+    let temp_dir = PathBuf::from(&upload.temp_dir);
+    let results_dir = PathBuf::from(&upload.results_dir);
+    let removed = tokio::task::spawn_blocking(move || {
+        remove_unreferenced(&temp_dir, &inputs) + remove_unreferenced(&results_dir, &result_paths)
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Leftover file cleanup panicked: {}", e);
+        0
+    });
+
+    tracing::info!(
+        "Janitor removed {} expired tasks and {} leftover files, keeping {} tasks",
+        expired.len(),
+        removed,
+        kept.len()
+    );
+    Ok(())
+}
+
+// Delete a task that no worker runs anymore, with its input, results and Redis keys
+// Failures are logged and the rest of the task is still removed
+pub async fn remove_task(redis_service: &RedisService, task: &TaskInfo) {
+    tracing::info!("Removing task {} of user {}", task.task_id, task.user);
+
+    // Later submissions must not be pointed at results about to be deleted
+    if let Some(cache) = task.cache.as_ref().filter(|cache| !cache.hit) {
+        match redis_service.get_cached_result(&cache.key).await {
+            Ok(Some(entry)) if entry.task_id == task.task_id => {
+                if let Err(e) = redis_service.remove_cached_result(&cache.key).await {
+                    tracing::error!("Failed to remove cache entry of task {}: {}", task.task_id, e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Failed to read cache entry of task {}: {}", task.task_id, e),
+        }
+    }
+
+    if let Err(e) = redis_service.remove_dead_letter_task(&task.task_id).await {
+        tracing::error!("Failed to remove task {} from the dead letter list: {}", task.task_id, e);
+    }
+
+    match tokio::fs::remove_file(&task.fasta_path).await {
+        Ok(()) => tracing::debug!("Deleted input file: {}", task.fasta_path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("Failed to delete input file {}: {}", task.fasta_path, e),
+    }
+    if !task.result_path.is_empty() {
+        match tokio::fs::remove_dir_all(&task.result_path).await {
+            Ok(()) => tracing::debug!("Deleted result directory: {}", task.result_path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to delete result directory {}: {}", task.result_path, e),
+        }
+    }
+
+    if let Err(e) = redis_service
+        .update_user(&task.user, |user| {
+            // Storage is only released once, by whoever takes the task off the user's list
+            if let Some(index) = user.tasks.iter().position(|t| t == &task.task_id) {
+                user.tasks.remove(index);
+                user.used_storage = user.used_storage.saturating_sub(task.storage_bytes);
+            }
+            user.release_active_task(&task.task_id);
+        })
+        .await
+    {
+        tracing::error!("Failed to remove task {} from user {}: {}", task.task_id, task.user, e);
+    }
+    if let Err(e) = redis_service.delete_task(&task.task_id).await {
+        tracing::error!("Failed to delete task {} from Redis: {}", task.task_id, e);
+    }
+}
+
+// Helper function to delete entries of <root>/<user>/ that are not in referenced and old enough
+// to no longer be in use: uploads of failed tasks and files left behind by interrupted requests
+// Returns the number of entries deleted
+fn remove_unreferenced(root: &Path, referenced: &HashSet<PathBuf>) -> usize {
+    let user_dirs = match std::fs::read_dir(root) {
+        Ok(user_dirs) => user_dirs,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::error!("Failed to list {}: {}", root.display(), e);
+            }
+            return 0;
+        }
+    };
+
+    let mut removed = 0;
+    for user_dir in user_dirs.flatten() {
+        let Ok(entries) = std::fs::read_dir(user_dir.path()) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = user_dir.path().join(entry.file_name());
+            if referenced.contains(&path) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .unwrap_or_default();
+            if age.as_secs() < LEFTOVER_GRACE_SECS {
+                continue;
+            }
+
+            let result = if metadata.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            };
+            match result {
+                Ok(()) => {
+                    tracing::info!("Deleted leftover {}", path.display());
+                    removed += 1;
+                }
+                Err(e) => tracing::warn!("Failed to delete leftover {}: {}", path.display(), e),
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::time::SystemTime;
+
+    // Helper function to age a file or directory past the grace period
+    fn make_old(path: &Path) {
+        let modified = SystemTime::now() - Duration::from_secs(LEFTOVER_GRACE_SECS + 60);
+        File::open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn remove_unreferenced_keeps_referenced_and_recent_entries() {
+        let root = std::env::temp_dir().join(format!("kmap-janitor-test-{}", std::process::id()));
+        let user_dir = root.join("alice");
+        fs::create_dir_all(user_dir.join("old_result")).unwrap();
+        for name in ["old_upload.fa", "recent_upload.fa", "queued_upload.fa"] {
+            fs::write(user_dir.join(name), ">s\nACGT\n").unwrap();
+        }
+        for name in ["old_upload.fa", "queued_upload.fa", "old_result"] {
+            make_old(&user_dir.join(name));
+        }
+        let referenced = HashSet::from([user_dir.join("queued_upload.fa")]);
+
+        assert_eq!(remove_unreferenced(&root, &referenced), 2);
+        assert!(!user_dir.join("old_upload.fa").exists());
+        assert!(!user_dir.join("old_result").exists());
+        assert!(user_dir.join("recent_upload.fa").exists());
+        assert!(user_dir.join("queued_upload.fa").exists());
+
+        // A missing root, such as a temp directory never written to, is not an error
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(remove_unreferenced(&root, &referenced), 0);
+    }
+}
@@ -1,2 +1,9 @@
+#[allow(clippy::module_inception)]
 mod worker;
-pub use worker::worker_process; 
\ No newline at end of file
+mod retry;
+mod checkpoint;
+mod janitor;
+mod quota_reset;
+pub use checkpoint::CHECKPOINT_DIR;
+pub use janitor::remove_task;
+pub use worker::{spawn_worker_pool, ShutdownPhase, WorkerPool, worker_process, scheduler_process, supervisor_process}; 
\ No newline at end of file
@@ -0,0 +1,149 @@
+use tokio::time::{sleep, Duration};
+use chrono::{DateTime, Utc};
+use crate::models::User;
+use crate::services::RedisService;
+use crate::config::Config;
+
+// How often users are checked for a due quota reset
+const QUOTA_RESET_CHECK_SECS: u64 = 300;
+
+// Periodically give users on plans with a reset period their quota back
+// Resets are checked again under update_user, so several processes never reset a user twice
+pub async fn quota_reset_process(redis_service: RedisService, config: Config) {
+    tracing::info!("Quota reset scheduler started");
+
+    loop {
+        reset_due_quotas(&redis_service, &config).await;
+        sleep(Duration::from_secs(QUOTA_RESET_CHECK_SECS)).await;
+    }
+}
+
+async fn reset_due_quotas(redis_service: &RedisService, config: &Config) {
+    let usernames = match redis_service.get_all_usernames().await {
+        Ok(usernames) => usernames,
+        Err(e) => {
+            tracing::error!("Failed to list users: {}", e);
+            return;
+        }
+    };
+
+    let now = Utc::now();
+    for username in usernames {
+        match redis_service.get_user(&username).await {
+            Ok(Some(user)) if reset_due(&user, config, now) => {}
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::error!("Failed to read user {}: {}", username, e);
+                continue;
+            }
+        }
+
+        let reset = redis_service
+            .update_user(&username, |user| apply_reset(user, config, now))
+            .await;
+        match reset {
+            Ok(Some(true)) => tracing::info!("Reset quota of user {}", username),
+            Ok(_) => tracing::debug!("Scheduled quota reset of user {}", username),
+            Err(e) => tracing::error!("Failed to reset quota of user {}: {}", username, e),
+        }
+    }
+}
+
+// Helper function to reset a user's used quota if it is due and schedule the next reset
+// Returns whether the used quota was reset
+fn apply_reset(user: &mut User, config: &Config, now: DateTime<Utc>) -> bool {
+    if !reset_due(user, config, now) {
+        return false;
+    }
+    let Some(period) = config.plan(user.plan.as_deref()).reset_period else {
+        return false;
+    };
+    // A plan that just got a reset period starts counting from now
+    let was_scheduled = user.quota_reset_at.is_some();
+    if was_scheduled {
+        user.used_quota = 0;
+    }
+    user.quota_reset_at = Some(period.next_reset(now));
+    was_scheduled
+}
+
+// Helper function to tell whether a user's quota should be reset, or its first reset scheduled
+fn reset_due(user: &User, config: &Config, now: DateTime<Utc>) -> bool {
+    config.plan(user.plan.as_deref()).reset_period.is_some()
+        && user.quota_reset_at.is_none_or(|reset_at| reset_at <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // Default configuration with a weekly plan added
+    fn config() -> Config {
+        let toml = format!(
+            "{}\n[[user.plans]]\nname = \"weekly\"\nquota = 3600\nmax_tasks = 5\nmax_upload_size = 1024\nstorage_quota = 1024\nreset_period = \"weekly\"\n",
+            include_str!("../../config/default.toml")
+        );
+        config::Config::builder()
+            .add_source(config::File::from_str(&toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn user(plan: Option<&str>, quota_reset_at: Option<DateTime<Utc>>) -> User {
+        User {
+            username: "alice".into(),
+            password_hash: String::new(),
+            tasks: Vec::new(),
+            active_tasks: Vec::new(),
+            used_quota: 1800,
+            reserved_quota: 0,
+            used_storage: 0,
+            plan: plan.map(str::to_string),
+            quota_reset_at,
+        }
+    }
+
+    #[test]
+    fn first_reset_is_only_scheduled() {
+        let config = config();
+        // 2026-10-14 is a Wednesday
+        let now = Utc.with_ymd_and_hms(2026, 10, 14, 12, 0, 0).unwrap();
+        let mut user = user(Some("weekly"), None);
+        assert!(reset_due(&user, &config, now));
+        assert!(!apply_reset(&mut user, &config, now));
+        assert_eq!(user.used_quota, 1800);
+        assert_eq!(user.quota_reset_at, Some(Utc.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap()));
+        assert!(!reset_due(&user, &config, now));
+    }
+
+    #[test]
+    fn due_reset_clears_used_quota_and_schedules_the_next() {
+        let config = config();
+        let reset_at = Utc.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap();
+        let mut user = user(Some("weekly"), Some(reset_at));
+        assert!(!apply_reset(&mut user, &config, reset_at - chrono::Duration::seconds(1)));
+        assert_eq!(user.used_quota, 1800);
+
+        // A scheduler that missed the reset catches up with the next Monday after now
+        let now = Utc.with_ymd_and_hms(2026, 10, 20, 9, 0, 0).unwrap();
+        assert!(apply_reset(&mut user, &config, now));
+        assert_eq!(user.used_quota, 0);
+        assert_eq!(user.quota_reset_at, Some(Utc.with_ymd_and_hms(2026, 10, 26, 0, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn plans_without_period_never_reset() {
+        let config = config();
+        let now = Utc.with_ymd_and_hms(2026, 10, 14, 12, 0, 0).unwrap();
+        for plan in [None, Some("removed")] {
+            let mut user = user(plan, None);
+            assert!(!reset_due(&user, &config, now));
+            assert!(!apply_reset(&mut user, &config, now));
+            assert_eq!(user.used_quota, 1800);
+            assert_eq!(user.quota_reset_at, None);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use tokio::time::Duration;
+use crate::config::WorkerConfig;
+use crate::errors::worker::WorkerError;
+
+// Decides which failed runs are tried again, and when
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: u64,  // in seconds
+    pub max_delay: u64,   // in seconds
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &WorkerConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts,
+            base_delay: config.retry_base_delay,
+            max_delay: config.retry_max_delay,
+        }
+    }
+
+    // Only infrastructure hiccups are worth another run; anything caused by the input,
+    // the parameters or the quota fails the same way every time
+    pub fn is_retryable(error: &WorkerError) -> bool {
+        match error {
+            WorkerError::Redis(_) | WorkerError::Io(_) => true,
+            WorkerError::Timeout(_)
+            | WorkerError::TaskPanic(_)
+            | WorkerError::FileNotFound(_)
+            | WorkerError::Processing(_)
+            | WorkerError::InvalidKmer(_)
+            | WorkerError::QuotaExceeded(_)
+            | WorkerError::Cancelled
+            | WorkerError::Interrupted => false,
+        }
+    }
+
+    pub fn has_attempts_left(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    // Exponential backoff after the given number of attempts: base, 2 * base, 4 * base, ...
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(31);
+        let delay = self.base_delay.saturating_mul(1 << exponent).min(self.max_delay);
+        Duration::from_secs(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { max_attempts: 3, base_delay: 30, max_delay: 100 }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = policy();
+        assert_eq!(policy.backoff(1), Duration::from_secs(30));
+        assert_eq!(policy.backoff(2), Duration::from_secs(60));
+        assert_eq!(policy.backoff(3), Duration::from_secs(100));
+        assert_eq!(policy.backoff(64), Duration::from_secs(100));
+        // A task that never started is retried after the base delay
+        assert_eq!(policy.backoff(0), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn attempts_are_bounded() {
+        let policy = policy();
+        assert!(policy.has_attempts_left(1));
+        assert!(policy.has_attempts_left(2));
+        assert!(!policy.has_attempts_left(3));
+    }
+
+    #[test]
+    fn only_infrastructure_failures_are_retried() {
+        let redis = redis::RedisError::from((redis::ErrorKind::IoError, "connection reset"));
+        assert!(RetryPolicy::is_retryable(&WorkerError::Redis(redis)));
+        assert!(RetryPolicy::is_retryable(&WorkerError::Io(std::io::Error::other("disk full"))));
+
+        assert!(!RetryPolicy::is_retryable(&WorkerError::Timeout(60)));
+        assert!(!RetryPolicy::is_retryable(&WorkerError::Processing("bad input".into())));
+        assert!(!RetryPolicy::is_retryable(&WorkerError::QuotaExceeded("alice".into())));
+        assert!(!RetryPolicy::is_retryable(&WorkerError::Cancelled));
+    }
+}
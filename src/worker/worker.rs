@@ -1,29 +1,203 @@
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
 use std::path::Path;
-use crate::models::{TaskInfo, TaskStatus, User, ProcessForm};
-use std::fs;
+use crate::models::{CachedResult, QuotaLedgerEntry, TaskInfo, TaskStatus, TaskProgress, TaskError, TaskErrorCode, RuntimeSample, ProcessForm, RunManifest, PipelineStage, WorkerHeartbeat};
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
-use tokio::sync::Semaphore;
-use crate::kmap_algorithms::kmer_count::{load_fasta, count_kmers_in_sequences, hash2kmer};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use crate::kmap_algorithms::kmer_count::{load_fasta, count_kmers_in_sequences, hash2kmer, KMER_LENGTH};
+use crate::kmap_algorithms::bed::{parse_bed, extract_sequences, GenomicInterval, WindowOptions};
+use crate::kmap_algorithms::site_scan::{scan_best_sites, DEFAULT_MIN_RELATIVE_SCORE};
+use crate::kmap_algorithms::motif_table::motif_def;
+use crate::kmap_algorithms::hamming_ball::hamming_ball_pwm;
+use crate::kmap_algorithms::em::{EmInput, refine_zoops};
+use crate::kmap_algorithms::motif::{Motif, MotifResults};
+use crate::kmap_algorithms::alphabet::Alphabet;
+use crate::kmap_algorithms::cluster::{cluster_motifs, DEFAULT_MAX_CLUSTER_DISTANCE};
+use crate::kmap_algorithms::motif_plot::plot_motif_tree;
 
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
-use anyhow::{Result, Context};
 use chrono::Utc;
-use crate::services::RedisService;
+use crate::services::{RedisService, estimator, storage};
+use crate::config::{Config, RetentionConfig, WorkerConfig};
+use super::retry::RetryPolicy;
+use super::janitor::{janitor_process, remove_task};
+use super::quota_reset::quota_reset_process;
+use super::checkpoint::{Checkpoint, stage_graph, INTERVALS_ARTIFACT, KMER_COUNTS_ARTIFACT, MOTIFS_ARTIFACT};
 use crate::errors::worker::{WorkerError, WorkerResult};
-use tracing;
+
+// Lifecycle of a worker pool; workers stop taking tasks once it leaves Running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShutdownPhase {
+    Running,
+    // No new tasks are taken, running ones may finish
+    Draining,
+    // Running tasks are stopped and handed back to the queue
+    Interrupting,
+}
+
+// How long interrupted tasks get to reach their next checkpoint before they are abandoned
+const INTERRUPT_TIMEOUT_SECS: u64 = 30;
+
+// Handle to the workers started by spawn_worker_pool, used to shut them down
+pub struct WorkerPool {
+    redis_service: RedisService,
+    retry_policy: RetryPolicy,
+    phase: watch::Sender<ShutdownPhase>,
+    worker_ids: Vec<String>,
+    workers: JoinSet<()>,
+    background: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    // Stop taking tasks, give running ones grace_period to finish, then interrupt them
+    // Whatever did not finish is re-queued without counting the attempt
+    pub async fn shutdown(mut self, grace_period: Duration) {
+        tracing::info!(
+            "Stopping workers, waiting up to {} seconds for running tasks",
+            grace_period.as_secs()
+        );
+        self.phase.send_replace(ShutdownPhase::Draining);
+        for handle in &self.background {
+            handle.abort();
+        }
+
+        if tokio::time::timeout(grace_period, wait_for_workers(&mut self.workers)).await.is_err() {
+            tracing::warn!("Grace period over, interrupting running tasks");
+            self.phase.send_replace(ShutdownPhase::Interrupting);
+
+            let interrupt_timeout = Duration::from_secs(INTERRUPT_TIMEOUT_SECS);
+            if tokio::time::timeout(interrupt_timeout, wait_for_workers(&mut self.workers)).await.is_err() {
+                tracing::error!("Workers did not stop in time, abandoning their tasks");
+                self.workers.abort_all();
+            }
+        }
+
+        // Hand back anything still held by our workers, including tasks taken by
+        // a blocking pop that was abandoned when the shutdown began
+        for worker_id in &self.worker_ids {
+            let task_ids = match self.redis_service.get_worker_tasks(worker_id).await {
+                Ok(task_ids) => task_ids,
+                Err(e) => {
+                    tracing::error!("Failed to list tasks of worker {}: {}", worker_id, e);
+                    continue;
+                }
+            };
+
+            let mut requeued = true;
+            for task_id in &task_ids {
+                if let Err(e) = requeue_interrupted_task(&self.redis_service, worker_id, task_id, &self.retry_policy).await {
+                    tracing::error!("Failed to re-queue task {} of worker {}: {}", task_id, worker_id, e);
+                    requeued = false;
+                }
+            }
+
+            // Leave workers with tasks left to the supervisor of another process
+            if requeued {
+                if let Err(e) = self.redis_service.unregister_worker(worker_id).await {
+                    tracing::error!("Failed to unregister worker {}: {}", worker_id, e);
+                }
+            }
+        }
+        tracing::info!("Workers stopped");
+    }
+}
+
+// Helper function to wait until every worker of a pool has returned
+async fn wait_for_workers(workers: &mut JoinSet<()>) {
+    while let Some(result) = workers.join_next().await {
+        if let Err(e) = result {
+            tracing::error!("Worker stopped abnormally: {}", e);
+        }
+    }
+}
+
+// Start the scheduler, the supervisor and the configured number of workers on the current runtime
+// Used by the web server for in-process workers and by the standalone kmap-worker binary
+pub fn spawn_worker_pool(redis_service: RedisService, config: &Config) -> WorkerPool {
+    // Limit how many tasks this process runs at once
+    let semaphore = Arc::new(Semaphore::new(config.worker.max_concurrent_tasks));
+    let (phase, phase_rx) = watch::channel(ShutdownPhase::Running);
+
+    let background = vec![
+        // Dispatch queued tasks to workers, taking users in turn
+        tokio::spawn(scheduler_process(redis_service.clone(), config.user.max_running_tasks)),
+        // Recover tasks of workers that stopped sending heartbeats, including those of earlier runs
+        tokio::spawn(supervisor_process(redis_service.clone(), config.worker.clone())),
+        // Delete expired results and leftover files
+        tokio::spawn(janitor_process(redis_service.clone(), config.retention.clone(), config.upload.clone())),
+        // Reset the used quota of users whose plan resets weekly or monthly
+        tokio::spawn(quota_reset_process(redis_service.clone(), config.clone())),
+    ];
+
+    // Worker ids are unique per process, so a restart never reuses a dead worker's id
+    let instance_id = uuid::Uuid::new_v4().simple().to_string();
+    let mut worker_ids = Vec::with_capacity(config.worker.worker_count);
+    let mut workers = JoinSet::new();
+    for index in 0..config.worker.worker_count {
+        let redis_service_worker = redis_service.clone();
+        let semaphore_worker = semaphore.clone();
+        let config_worker = config.clone();
+        let worker_id = format!("worker-{}-{}", instance_id, index);
+        let phase_worker = phase_rx.clone();
+        worker_ids.push(worker_id.clone());
+        workers.spawn(async move {
+            worker_process(redis_service_worker, semaphore_worker, worker_id, config_worker, phase_worker).await;
+        });
+    }
+    tracing::info!(
+        "Started {} workers running up to {} tasks at once",
+        config.worker.worker_count,
+        config.worker.max_concurrent_tasks
+    );
+
+    WorkerPool {
+        redis_service,
+        retry_policy: RetryPolicy::from_config(&config.worker),
+        phase,
+        worker_ids,
+        workers,
+        background,
+    }
+}
+
+// How long a blocking pop waits on an empty queue before it is reissued
+const QUEUE_BLOCK_TIMEOUT_SECS: f64 = 30.0;
 
 pub async fn worker_process(
     redis_service: RedisService,
     semaphore: Arc<Semaphore>,
+    worker_id: String,
+    config: Config,
+    mut phase: watch::Receiver<ShutdownPhase>,
 ) {
-    tracing::info!("Worker started");
+    tracing::info!("Worker {} started", worker_id);
+    let retry_policy = RetryPolicy::from_config(&config.worker);
+
+    // Publish heartbeats from a separate task; it stops once current_task is dropped,
+    // including when this worker panics, so the supervisor can recover our task
+    let (current_task, current_task_rx) = watch::channel(None::<String>);
+    tokio::spawn(heartbeat_process(
+        redis_service.clone(),
+        worker_id.clone(),
+        current_task_rx,
+        config.worker.heartbeat_ttl,
+    ));
     
-    loop {
-        // First acquire the semaphore before popping a task
-        let _permit = match semaphore.acquire().await {
+    // Connection reserved for blocking pops, reopened after errors
+    let mut queue_conn = None;
+
+    while *phase.borrow() == ShutdownPhase::Running {
+        // Wait for a free slot first; an idle worker then sits in a blocking pop
+        // and takes the next task as soon as it is queued
+        let acquired = tokio::select! {
+            acquired = semaphore.acquire() => acquired,
+            _ = phase.wait_for(|phase| *phase != ShutdownPhase::Running) => break,
+        };
+        let _permit = match acquired {
             Ok(permit) => permit,
             Err(e) => {
                 tracing::error!("Failed to acquire semaphore: {}", e);
@@ -32,123 +206,679 @@ pub async fn worker_process(
             }
         };
 
+        let conn = match queue_conn.as_mut() {
+            Some(conn) => conn,
+            None => match redis_service.dedicated_connection().await {
+                Ok(conn) => queue_conn.insert(conn),
+                Err(e) => {
+                    tracing::error!("Worker {} failed to connect to the queue: {}", worker_id, e);
+                    drop(_permit);
+                    sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+        };
+
         // Try to get a task from the queue only after we have the semaphore
-        match redis_service.pop_task().await {
-            Ok(Some(task)) => {
-                let task_id = task.task_id.clone();
-                let username = task.user.clone();
-                
-                tracing::debug!("Processing task {} for user {}", task_id, username);
+        // A pop abandoned on shutdown may still take a task; the pool re-queues it afterwards
+        let dequeued = tokio::select! {
+            dequeued = redis_service.dequeue_task(conn, &worker_id, QUEUE_BLOCK_TIMEOUT_SECS) => dequeued,
+            _ = phase.wait_for(|phase| *phase != ShutdownPhase::Running) => break,
+        };
+        match dequeued {
+            Ok(Some(task_id)) => {
+                tracing::debug!("Worker {} took task {}", worker_id, task_id);
+                current_task.send_replace(Some(task_id.clone()));
                 
                 // Use a closure to handle the task processing with proper cleanup
                 let process_result = async {
-                    // Update task status to Processing
-                    update_task_status(&redis_service, &task_id, TaskStatus::Processing).await?;
-                    
-                    // Get user's remaining quota
-                    let remaining_quota = get_user_quota(&redis_service, &username).await?;
+                    // Reserve quota, count the attempt and update task status to Processing
+                    let task = start_task(&redis_service, &config, &task_id).await?;
                     
-                    // Execute task
-                    process_task_with_timeout(&task, remaining_quota).await
+                    // Execute task, for no longer than the reserved quota
+                    process_task_with_timeout(&redis_service, &task, task.reserved_quota, phase.clone()).await
                 }.await;
 
-                // Handle the result of task processing
-                match process_result {
-                    Ok(result) => {
-                        tracing::info!("Task {} completed successfully", task_id);
-                        if let Err(e) = update_task_result_user_quota(
-                            &redis_service,
-                            &task_id,
-                            TaskStatus::Completed,
-                            Some(result)
-                        ).await {
-                            tracing::error!("Failed to update task result: {}", e);
+                // Record the outcome; Redis outages are waited out so that it is not lost
+                loop {
+                    match finish_task(&redis_service, &worker_id, &task_id, &process_result, &retry_policy, &config.retention).await {
+                        Ok(()) => break,
+                        Err(WorkerError::Redis(e)) => {
+                            tracing::error!("Failed to record outcome of task {}, retrying: {}", task_id, e);
+                            sleep(Duration::from_secs(1)).await;
                         }
-                    }
-                    Err(e) => {
-                        tracing::error!("Task {} failed: {}", task_id, e);
-                        if let Err(update_err) = update_task_result_user_quota(
-                            &redis_service,
-                            &task_id,
-                            TaskStatus::Failed,
-                            None
-                        ).await {
-                            tracing::error!("Failed to update task status after error: {}", update_err);
+                        Err(e) => {
+                            tracing::error!("Failed to record outcome of task {}: {}", task_id, e);
+                            break;
                         }
                     }
                 }
+
+                // Acknowledge only once the outcome is saved, so a crash before this point
+                // leaves the task in our processing list for recovery
+                if let Err(e) = redis_service.ack_task(&worker_id, &task_id).await {
+                    tracing::error!("Failed to acknowledge task {}: {}", task_id, e);
+                }
+                if let Err(e) = redis_service.clear_cancel_request(&task_id).await {
+                    tracing::warn!("Failed to clear cancel request of task {}: {}", task_id, e);
+                }
+                remove_if_deleted(&redis_service, &task_id).await;
+                current_task.send_replace(None);
             }
             Ok(None) => {
-                // No tasks in queue, drop the permit and wait before checking again
+                // Queue stayed empty for the whole blocking timeout
                 drop(_permit);
-                sleep(Duration::from_secs(1)).await;
             }
             Err(e) => {
                 tracing::error!("Failed to pop task from queue: {}", e);
-                // Drop the permit and wait before retrying
+                // Reconnect on the next round, after dropping the permit and waiting
+                queue_conn = None;
                 drop(_permit);
                 sleep(Duration::from_secs(1)).await;
             }
         }
     }
+    tracing::info!("Worker {} stopped", worker_id);
+}
+
+// Helper function to record the outcome of a run and decide what happens to the task next
+// Transient failures are retried with backoff until the policy gives up, then dead-lettered
+async fn finish_task(
+    redis_service: &RedisService,
+    worker_id: &str,
+    task_id: &str,
+    process_result: &WorkerResult<HashMap<String, u32>>,
+    retry_policy: &RetryPolicy,
+    retention: &RetentionConfig,
+) -> WorkerResult<()> {
+    match process_result {
+        Ok(result) => {
+            tracing::info!("Task {} completed successfully", task_id);
+            let task = update_task_result_user_quota(
+                redis_service,
+                task_id,
+                TaskStatus::Completed,
+                Some(result.clone()),
+                None
+            ).await?;
+            remove_input_file(&task).await;
+            cache_task_result(redis_service, &task, retention).await;
+        }
+        Err(WorkerError::Cancelled) => {
+            tracing::info!("Task {} cancelled", task_id);
+            let task = update_task_result_user_quota(
+                redis_service,
+                task_id,
+                TaskStatus::Cancelled,
+                None,
+                None
+            ).await?;
+            remove_input_file(&task).await;
+        }
+        Err(WorkerError::Interrupted) => {
+            tracing::warn!("Task {} interrupted by shutdown", task_id);
+            requeue_interrupted_task(redis_service, worker_id, task_id, retry_policy).await?;
+        }
+        Err(e) if RetryPolicy::is_retryable(e) => {
+            let attempts = redis_service
+                .get_task(task_id)
+                .await?
+                .ok_or_else(|| WorkerError::Processing(format!("Task {} not found", task_id)))?
+                .attempts;
+
+            if retry_policy.has_attempts_left(attempts) {
+                let delay = retry_policy.backoff(attempts);
+                tracing::warn!(
+                    "Task {} failed on attempt {}, retrying in {} seconds: {}",
+                    task_id,
+                    attempts,
+                    delay.as_secs(),
+                    e
+                );
+                retry_task(redis_service, worker_id, task_id, e, delay).await?;
+            } else {
+                // The input is kept so that an admin can re-queue the task
+                tracing::error!("Task {} failed after {} attempts, dead-lettering it: {}", task_id, attempts, e);
+                update_task_result_user_quota(
+                    redis_service,
+                    task_id,
+                    TaskStatus::Failed,
+                    None,
+                    Some(e.to_task_error())
+                ).await?;
+                redis_service.add_dead_letter_task(task_id).await?;
+            }
+        }
+        Err(e) => {
+            tracing::error!("Task {} failed: {}", task_id, e);
+            let task = update_task_result_user_quota(
+                redis_service,
+                task_id,
+                TaskStatus::Failed,
+                None,
+                Some(e.to_task_error())
+            ).await?;
+            remove_input_file(&task).await;
+        }
+    }
+    Ok(())
+}
+
+// Helper function to offer a completed task's results to later identical submissions
+// A failure only costs a cache miss, so it is logged and otherwise ignored
+async fn cache_task_result(redis_service: &RedisService, task: &TaskInfo, retention: &RetentionConfig) {
+    let Some(cache) = task.cache.as_ref().filter(|cache| !cache.hit) else {
+        return;
+    };
+    let entry = CachedResult {
+        task_id: task.task_id.clone(),
+        result_path: task.result_path.clone(),
+        result: task.result.clone().unwrap_or_default(),
+        seed: task.params.seed,
+        created_at: Utc::now(),
+    };
+    match redis_service.set_cached_result(&cache.key, &entry, retention.result_ttl).await {
+        Ok(()) => tracing::debug!("Cached results of task {}", task.task_id),
+        Err(e) => tracing::warn!("Failed to cache results of task {}: {}", task.task_id, e),
+    }
+}
+
+// Helper function to charge a failed attempt and schedule the task to run again after delay
+async fn retry_task(
+    redis_service: &RedisService,
+    worker_id: &str,
+    task_id: &str,
+    error: &WorkerError,
+    delay: Duration,
+) -> WorkerResult<()> {
+    let mut task = redis_service
+        .get_task(task_id)
+        .await?
+        .ok_or_else(|| WorkerError::Processing(format!("Task {} not found", task_id)))?;
+
+    let seconds_used = task.start_time.map_or(0, |start_time| {
+        Utc::now().signed_duration_since(start_time).num_seconds().max(0) as u64
+    });
+    settle_quota(redis_service, &mut task, seconds_used).await?;
+
+    // The last error stays visible while the task waits for its next attempt
+    task.status = TaskStatus::Queued;
+    task.error = Some(error.to_task_error());
+    redis_service.save_task(&task).await?;
+
+    let due_timestamp = Utc::now().timestamp() + delay.as_secs() as i64;
+    redis_service.delay_task(worker_id, task_id, due_timestamp).await?;
+    Ok(())
+}
+
+// Helper function to remove a task its user deleted while it ran, now that its quota is settled
+// A task that is to run again keeps its mark and is removed after its next run
+async fn remove_if_deleted(redis_service: &RedisService, task_id: &str) {
+    let task = match redis_service.is_marked_deleted(task_id).await {
+        Ok(true) => redis_service.get_task(task_id).await,
+        Ok(false) => return,
+        Err(e) => Err(e),
+    };
+    match task {
+        Ok(Some(task)) if matches!(task.status, TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Cancelled) => {
+            tracing::info!("Task {} was deleted while it ran", task_id);
+            remove_task(redis_service, &task).await;
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!("Failed to check whether task {} was deleted: {}", task_id, e),
+    }
+}
+
+// Helper function to delete an uploaded input once its task is over
+async fn remove_input_file(task: &TaskInfo) {
+    match tokio::fs::remove_file(&task.fasta_path).await {
+        Ok(()) => tracing::info!("Deleted input file: {}", task.fasta_path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("Failed to delete input file {}: {}", task.fasta_path, e),
+    }
+}
+
+// Keep a worker's heartbeat alive, refreshing it a few times per TTL and whenever its task changes
+async fn heartbeat_process(
+    redis_service: RedisService,
+    worker_id: String,
+    mut current_task: watch::Receiver<Option<String>>,
+    heartbeat_ttl: u64,
+) {
+    let refresh_interval = Duration::from_secs((heartbeat_ttl / 3).max(1));
+
+    loop {
+        let heartbeat = WorkerHeartbeat {
+            worker_id: worker_id.clone(),
+            task_id: current_task.borrow_and_update().clone(),
+            timestamp: Utc::now(),
+        };
+
+        // Heartbeat first, so the supervisor never sees a registered worker without one
+        if let Err(e) = redis_service.set_heartbeat(&heartbeat, heartbeat_ttl).await {
+            tracing::error!("Failed to publish heartbeat of worker {}: {}", worker_id, e);
+        } else if let Err(e) = redis_service.register_worker(&worker_id).await {
+            tracing::error!("Failed to register worker {}: {}", worker_id, e);
+        }
+
+        tokio::select! {
+            changed = current_task.changed() => {
+                if changed.is_err() {
+                    tracing::warn!("Worker {} stopped, no longer sending heartbeats", worker_id);
+                    return;
+                }
+            }
+            _ = sleep(refresh_interval) => {}
+        }
+    }
+}
+
+// Dispatched tasks waiting for a worker; kept small so fairness is decided as late as possible
+const READY_QUEUE_LIMIT: usize = 1;
+
+// How long the scheduler sleeps without a wakeup before looking at the queues anyway
+const SCHEDULER_WAKEUP_TIMEOUT_SECS: f64 = 5.0;
+
+// Feed the workers' ready queue from the per-user queues, serving users in turn
+// Woken up whenever a task is queued, taken by a worker or finished
+pub async fn scheduler_process(redis_service: RedisService, max_running_tasks: Option<usize>) {
+    tracing::info!("Scheduler started");
+
+    // Connection reserved for blocking on wakeups, reopened after errors
+    let mut wakeup_conn = None;
+
+    loop {
+        promote_due_tasks(&redis_service).await;
+
+        match redis_service.dispatch_next_task(READY_QUEUE_LIMIT, max_running_tasks.unwrap_or(0)).await {
+            Ok(Some(task_id)) => {
+                tracing::debug!("Dispatched task {}", task_id);
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!("Failed to dispatch task: {}", e);
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        }
+
+        let conn = match wakeup_conn.as_mut() {
+            Some(conn) => conn,
+            None => match redis_service.dedicated_connection().await {
+                Ok(conn) => wakeup_conn.insert(conn),
+                Err(e) => {
+                    tracing::error!("Scheduler failed to connect: {}", e);
+                    sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+        };
+
+        if let Err(e) = redis_service.wait_for_scheduler_wakeup(conn, SCHEDULER_WAKEUP_TIMEOUT_SECS).await {
+            tracing::error!("Failed to wait for scheduler wakeup: {}", e);
+            wakeup_conn = None;
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+// Helper function to queue delayed retries whose backoff has elapsed
+async fn promote_due_tasks(redis_service: &RedisService) {
+    let task_ids = match redis_service.get_due_tasks(Utc::now().timestamp()).await {
+        Ok(task_ids) => task_ids,
+        Err(e) => {
+            tracing::error!("Failed to list due retries: {}", e);
+            return;
+        }
+    };
+
+    for task_id in task_ids {
+        match redis_service.get_task(&task_id).await {
+            Ok(Some(task)) => match redis_service.promote_delayed_task(&task).await {
+                Ok(()) => tracing::info!("Re-queued task {} for another attempt", task_id),
+                Err(e) => tracing::error!("Failed to re-queue task {}: {}", task_id, e),
+            },
+            Ok(None) => tracing::warn!("Delayed task {} no longer exists", task_id),
+            Err(e) => tracing::error!("Failed to fetch delayed task {}: {}", task_id, e),
+        }
+    }
+}
+
+// Periodically recover tasks held by workers whose heartbeat has expired
+pub async fn supervisor_process(redis_service: RedisService, worker_config: WorkerConfig) {
+    tracing::info!("Supervisor started");
+    let retry_policy = RetryPolicy::from_config(&worker_config);
+
+    loop {
+        recover_dead_workers(&redis_service, &retry_policy).await;
+        sleep(Duration::from_secs(worker_config.supervisor_interval)).await;
+    }
+}
+
+// Send the tasks of every dead worker back to the queue, or dead-letter them once they used up their attempts
+async fn recover_dead_workers(redis_service: &RedisService, retry_policy: &RetryPolicy) {
+    let workers = match redis_service.get_workers().await {
+        Ok(workers) => workers,
+        Err(e) => {
+            tracing::error!("Failed to list registered workers: {}", e);
+            return;
+        }
+    };
+
+    for worker_id in workers {
+        match redis_service.get_heartbeat(&worker_id).await {
+            Ok(Some(_)) => continue,
+            Ok(None) => tracing::warn!("Heartbeat of worker {} expired", worker_id),
+            Err(e) => {
+                tracing::error!("Failed to read heartbeat of worker {}: {}", worker_id, e);
+                continue;
+            }
+        }
+
+        let task_ids = match redis_service.get_worker_tasks(&worker_id).await {
+            Ok(task_ids) => task_ids,
+            Err(e) => {
+                tracing::error!("Failed to list tasks of worker {}: {}", worker_id, e);
+                continue;
+            }
+        };
+
+        let mut recovered = true;
+        for task_id in &task_ids {
+            if let Err(e) = recover_task(redis_service, &worker_id, task_id, retry_policy).await {
+                tracing::error!("Failed to recover task {} of worker {}: {}", task_id, worker_id, e);
+                recovered = false;
+            }
+        }
+
+        // Forget the worker only once nothing of it is left to recover
+        if recovered {
+            if let Err(e) = redis_service.unregister_worker(&worker_id).await {
+                tracing::error!("Failed to unregister worker {}: {}", worker_id, e);
+            }
+        }
+    }
+}
+
+// Helper function to re-queue or fail a single task taken from a dead worker
+async fn recover_task(
+    redis_service: &RedisService,
+    worker_id: &str,
+    task_id: &str,
+    retry_policy: &RetryPolicy,
+) -> WorkerResult<()> {
+    let mut task = match redis_service.get_task(task_id).await.map_err(WorkerError::Redis)? {
+        Some(task) => task,
+        None => {
+            // Deleted tasks are kept until settled, so this one has nothing left to release
+            tracing::warn!("Dropping unknown task {} of worker {}", task_id, worker_id);
+            return redis_service.ack_task(worker_id, task_id).await.map_err(WorkerError::Redis);
+        }
+    };
+    let attempts = task.attempts;
+
+    // Release the dead run's reservation; users do not pay for runs lost to a crash
+    if task.reserved_quota > 0 {
+        settle_quota(redis_service, &mut task, 0).await?;
+        redis_service.save_task(&task).await.map_err(WorkerError::Redis)?;
+    }
+
+    // A deleted task is never run again, even once its cancel request has expired
+    let cancel_requested = redis_service
+        .is_cancel_requested(task_id)
+        .await
+        .map_err(WorkerError::Redis)?
+        || redis_service
+            .is_marked_deleted(task_id)
+            .await
+            .map_err(WorkerError::Redis)?;
+
+    if cancel_requested {
+        tracing::info!("Task {} was cancelled while its worker was down", task_id);
+        let task = update_task_result_user_quota(redis_service, task_id, TaskStatus::Cancelled, None, None).await?;
+        remove_input_file(&task).await;
+        redis_service.ack_task(worker_id, task_id).await.map_err(WorkerError::Redis)?;
+        redis_service.clear_cancel_request(task_id).await.map_err(WorkerError::Redis)?;
+        remove_if_deleted(redis_service, task_id).await;
+        Ok(())
+    } else if !retry_policy.has_attempts_left(attempts) {
+        tracing::warn!("Task {} failed after {} attempts", task_id, attempts);
+        let error = TaskError::new(
+            TaskErrorCode::Internal,
+            format!("The worker running this task stopped responding {} times", attempts),
+        );
+        update_task_result_user_quota(redis_service, task_id, TaskStatus::Failed, None, Some(error)).await?;
+        redis_service.add_dead_letter_task(task_id).await.map_err(WorkerError::Redis)?;
+        redis_service.ack_task(worker_id, task_id).await.map_err(WorkerError::Redis)
+    } else {
+        tracing::warn!("Re-queueing task {} after {} attempt(s)", task_id, attempts);
+        update_task_status(redis_service, task_id, TaskStatus::Queued).await?;
+        redis_service.requeue_worker_task(worker_id, &task).await.map_err(WorkerError::Redis)
+    }
+}
+
+// Helper function to hand back a task this process could not finish before shutting down
+// The interrupted run is not counted as an attempt; a pending cancellation still applies
+async fn requeue_interrupted_task(
+    redis_service: &RedisService,
+    worker_id: &str,
+    task_id: &str,
+    retry_policy: &RetryPolicy,
+) -> WorkerResult<()> {
+    if let Some(mut task) = redis_service.get_task(task_id).await.map_err(WorkerError::Redis)? {
+        // Tasks taken by an abandoned pop never started, so they have no attempt to undo
+        if matches!(task.status, TaskStatus::Processing) {
+            task.attempts = task.attempts.saturating_sub(1);
+            redis_service.save_task(&task).await.map_err(WorkerError::Redis)?;
+        }
+    }
+    recover_task(redis_service, worker_id, task_id, retry_policy).await
 }
 
 // Helper function to get user's remaining quota
-async fn get_user_quota(redis_service: &RedisService, username: &str) -> WorkerResult<u64> {
-    let user = redis_service
-        .get_user(username)
+// The quota comes from the user's plan as configured now, so plan changes apply to existing users
+async fn reserve_quota(
+    redis_service: &RedisService,
+    config: &Config,
+    username: &str,
+) -> WorkerResult<u64> {
+    // Atomic, so concurrent tasks of one user can never reserve more than is left
+    let reserved = redis_service
+        .update_user(username, |user| {
+            let quota = config.plan(user.plan.as_deref()).quota;
+            let reserved = user.available_quota(quota).min(config.worker.max_task_duration);
+            user.reserved_quota += reserved;
+            reserved
+        })
         .await
         .map_err(WorkerError::Redis)?
         .ok_or_else(|| WorkerError::Processing(format!("User {} not found", username)))?;
 
-    let remaining_quota = (user.quota - user.used_quota).max(0) as u64;
-    tracing::debug!("Remaining quota for user {}: {} seconds", username, remaining_quota);
+    tracing::debug!("Reserved {} seconds of quota for user {}", reserved, username);
 
-    if remaining_quota == 0 {
+    if reserved == 0 {
         return Err(WorkerError::QuotaExceeded(username.to_string()));
     }
 
-    Ok(remaining_quota)
+    Ok(reserved)
+}
+
+// Helper function to charge a task's run against its reservation and release the rest
+// Every settlement is recorded in the user's quota ledger
+async fn settle_quota(redis_service: &RedisService, task: &mut TaskInfo, seconds_used: u64) -> WorkerResult<()> {
+    if task.reserved_quota == 0 {
+        return Ok(());
+    }
+    let reserved = task.reserved_quota;
+    let charged = seconds_used.min(reserved);
+
+    redis_service
+        .update_user(&task.user, |user| {
+            user.used_quota += charged;
+            user.reserved_quota = user.reserved_quota.saturating_sub(reserved);
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update quota for user {}: {}", task.user, e);
+            WorkerError::Redis(e)
+        })?
+        .ok_or_else(|| WorkerError::Processing(format!("User {} not found", task.user)))?;
+    task.reserved_quota = 0;
+
+    let entry = QuotaLedgerEntry {
+        task_id: task.task_id.clone(),
+        reserved,
+        charged,
+        timestamp: Utc::now(),
+    };
+    if let Err(e) = redis_service.append_quota_ledger(&task.user, &entry).await {
+        tracing::error!("Failed to record quota ledger entry for task {}: {}", task.task_id, e);
+    }
+
+    tracing::info!(
+        "Charged user {} {} of {} reserved seconds for task {}",
+        task.user,
+        charged,
+        reserved,
+        task.task_id
+    );
+    Ok(())
+}
+
+// Helper function to stop counting a finished task against its user's active task limit
+async fn release_active_task(redis_service: &RedisService, task: &TaskInfo) -> WorkerResult<()> {
+    redis_service
+        .update_user(&task.user, |user| user.release_active_task(&task.task_id))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to release active task {} of user {}: {}", task.task_id, task.user, e);
+            WorkerError::Redis(e)
+        })?;
+    Ok(())
+}
+
+// Helper function to charge the size of a finished task's results to its user's storage quota
+// Only the change since the last charge is applied, so re-queued tasks are not charged twice
+async fn charge_storage(redis_service: &RedisService, task: &mut TaskInfo) -> WorkerResult<()> {
+    let storage_bytes = storage::dir_size(Path::new(&task.result_path));
+    let previous_bytes = task.storage_bytes;
+    if storage_bytes == previous_bytes {
+        return Ok(());
+    }
+
+    redis_service
+        .update_user(&task.user, |user| {
+            user.used_storage = user.used_storage.saturating_sub(previous_bytes) + storage_bytes;
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update storage for user {}: {}", task.user, e);
+            WorkerError::Redis(e)
+        })?
+        .ok_or_else(|| WorkerError::Processing(format!("User {} not found", task.user)))?;
+    task.storage_bytes = storage_bytes;
+
+    tracing::info!("Charged user {} {} bytes of storage for task {}", task.user, storage_bytes, task.task_id);
+    Ok(())
 }
 
+// How often a running task checks whether the user cancelled it
+const CANCEL_POLL_INTERVAL_SECS: u64 = 2;
+
+// Progress is written to Redis at most this often
+const PROGRESS_SAVE_INTERVAL_SECS: u64 = 1;
+
 // Process task with timeout
 async fn process_task_with_timeout(
+    redis_service: &RedisService,
     task: &TaskInfo,
     remaining_quota: u64,
+    phase: watch::Receiver<ShutdownPhase>,
 ) -> WorkerResult<HashMap<String, u32>> {
     let task_path = task.fasta_path.clone();
-    let task_path_delete = task.fasta_path.clone();
     let task_params = task.params.clone();
     let result_path = task.result_path.clone();
+    let reference_fasta = task.reference_fasta.clone();
 
     tracing::debug!(
         "Starting task processing with timeout of {} seconds",
         remaining_quota
     );
 
+    // Record how to reproduce this run before computing, so a failure to write it cannot
+    // replace the outcome of the computation
+    save_manifest_to_file(task)?;
+
+    // Raised when the user cancels the task; the computation checks it between steps
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancel_watcher = tokio::spawn(watch_cancel_request(
+        redis_service.clone(),
+        task.task_id.clone(),
+        cancelled.clone(),
+    ));
+
+    // Raised when the pool interrupts running tasks on shutdown; stops the computation the same way
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupt_watcher = tokio::spawn(watch_interrupt(phase, cancelled.clone(), interrupted.clone()));
+
+    // The computation reports progress through a callback; a separate task stores it
+    let (progress_tx, progress_rx) = watch::channel(TaskProgress {
+        stage: "Starting".to_string(),
+        percent: 0.0,
+        eta_seconds: None,
+        updated_at: Utc::now(),
+    });
+    tokio::spawn(save_progress_updates(
+        redis_service.clone(),
+        task.task_id.clone(),
+        progress_rx,
+    ));
+
     // Spawn the task in a separate task to catch panics
+    let task_cancelled = cancelled.clone();
     let result = tokio::time::timeout(
         Duration::from_secs(remaining_quota),
-        tokio::spawn(async move {
+        // CPU-bound work runs on the blocking pool so heartbeats keep flowing
+        tokio::task::spawn_blocking(move || {
+            let started = Instant::now();
+            let report_progress = move |stage: &str, percent: f64| {
+                // Extrapolate from the pace so far
+                let elapsed = started.elapsed().as_secs_f64();
+                let eta_seconds = (percent > 0.0)
+                    .then(|| (elapsed * (100.0 - percent) / percent).round() as u64);
+                progress_tx.send_replace(TaskProgress {
+                    stage: stage.to_string(),
+                    percent,
+                    eta_seconds,
+                    updated_at: Utc::now(),
+                });
+            };
+
             process_task(
                 Path::new(&task_path), 
                 &task_params, 
-                Path::new(&result_path)
-            ).await
+                Path::new(&result_path),
+                reference_fasta.as_deref().map(Path::new),
+                &task_cancelled,
+                &report_progress,
+            )
         })
     ).await;
 
-    // Delete the FASTA file after processing, regardless of the result
-    if let Err(e) = tokio::fs::remove_file(&task_path_delete).await {
-        // Map the IO error to a WorkerError
-        return Err(WorkerError::Io(e));
-    }
-    tracing::info!("Successfully deleted FASTA file: {}", task_path_delete);
+    // A timed out computation keeps running on its thread until told to stop
+    cancel_watcher.abort();
+    interrupt_watcher.abort();
+    cancelled.store(true, Ordering::Relaxed);
 
     // Handle all possible error cases
     match result {
         Ok(spawn_result) => {
             match spawn_result {
+                // Stopped by the shutdown rather than by the user
+                Ok(Err(WorkerError::Cancelled)) if interrupted.load(Ordering::Relaxed) => {
+                    Err(WorkerError::Interrupted)
+                }
                 Ok(task_result) => task_result,
                 Err(e) => {
                     tracing::error!("Task panicked: {}", e);
@@ -163,10 +893,106 @@ async fn process_task_with_timeout(
     }
 }
 
-async fn process_task(
+// Helper function to store progress reports until the computation finishes
+// Reports arriving faster than PROGRESS_SAVE_INTERVAL_SECS are coalesced, keeping only the latest
+async fn save_progress_updates(
+    redis_service: RedisService,
+    task_id: String,
+    mut progress: watch::Receiver<TaskProgress>,
+) {
+    while progress.changed().await.is_ok() {
+        let latest = progress.borrow_and_update().clone();
+        if let Err(e) = redis_service.save_progress(&task_id, &latest).await {
+            tracing::warn!("Failed to save progress of task {}: {}", task_id, e);
+        }
+        sleep(Duration::from_secs(PROGRESS_SAVE_INTERVAL_SECS)).await;
+    }
+}
+
+// Helper function to raise the cancel flag once the user asks for cancellation
+async fn watch_cancel_request(redis_service: RedisService, task_id: String, cancelled: Arc<AtomicBool>) {
+    loop {
+        match redis_service.is_cancel_requested(&task_id).await {
+            Ok(true) => {
+                tracing::info!("Cancellation requested for task {}", task_id);
+                cancelled.store(true, Ordering::Relaxed);
+                return;
+            }
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Failed to check cancellation of task {}: {}", task_id, e),
+        }
+        sleep(Duration::from_secs(CANCEL_POLL_INTERVAL_SECS)).await;
+    }
+}
+
+// Helper function to raise the cancel flag once the pool starts interrupting running tasks
+async fn watch_interrupt(
+    mut phase: watch::Receiver<ShutdownPhase>,
+    cancelled: Arc<AtomicBool>,
+    interrupted: Arc<AtomicBool>,
+) {
+    if phase.wait_for(|phase| *phase == ShutdownPhase::Interrupting).await.is_ok() {
+        interrupted.store(true, Ordering::Relaxed);
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+// Helper function to stop a computation between steps once it was cancelled
+fn check_cancelled(cancelled: &AtomicBool) -> WorkerResult<()> {
+    if cancelled.load(Ordering::Relaxed) {
+        Err(WorkerError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+// Helper function to write manifest.json, the record needed to reproduce a run
+// It holds no task id or timestamps so that identical runs write identical manifests
+fn save_manifest_to_file(task: &TaskInfo) -> WorkerResult<()> {
+    let input_sha256 = match &task.input_sha256 {
+        Some(sha256) => sha256.clone(),
+        None => {
+            let input = std::fs::read(&task.fasta_path)
+                .map_err(|e| {
+                    tracing::error!("Failed to read input {}: {}", task.fasta_path, e);
+                    WorkerError::Io(e)
+                })?;
+            format!("{:x}", Sha256::digest(&input))
+        }
+    };
+
+    let manifest = RunManifest {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        seed: task.params.seed.unwrap_or_default(),
+        input_filename: task.filename.clone(),
+        input_sha256,
+        params: task.params.clone(),
+        stages: stage_graph(task.reference_fasta.is_some()),
+    };
+
+    let manifest_path = Path::new(&task.result_path).join("manifest.json");
+    let file = File::create(&manifest_path)
+        .map_err(|e| {
+            tracing::error!("Failed to create file {}: {}", manifest_path.display(), e);
+            WorkerError::Io(e)
+        })?;
+    serde_json::to_writer_pretty(file, &manifest)
+        .map_err(|e| {
+            tracing::error!("Failed to write run manifest: {}", e);
+            WorkerError::Processing(format!("Failed to write run manifest: {}", e))
+        })?;
+
+    tracing::debug!("Saved run manifest to {}", manifest_path.display());
+    Ok(())
+}
+
+fn process_task(
     fasta_path: &std::path::Path, 
-    _form: &ProcessForm,
+    form: &ProcessForm,
     result_path: &std::path::Path,
+    reference_fasta: Option<&std::path::Path>,
+    cancelled: &AtomicBool,
+    progress: &dyn Fn(&str, f64),
 ) -> WorkerResult<HashMap<String, u32>> {
     // Check if file exists first
     if !fasta_path.exists() {
@@ -186,26 +1012,86 @@ async fn process_task(
             ))
         })?;
 
-    tracing::debug!("Loading FASTA file: {}", fasta_path_str);
+    // Stages completed by an earlier attempt of this task are skipped
+    let mut checkpoint = Checkpoint::load(result_path)?;
+
+    // BED uploads are turned into a FASTA file of the reference sequence under each interval
+    let (input_fasta, intervals) = match reference_fasta {
+        Some(_) if checkpoint.is_complete(PipelineStage::ExtractSequences) => {
+            let extracted_path = result_path.join("extracted_sequences.fa").display().to_string();
+            let intervals: Vec<GenomicInterval> = checkpoint.load_artifact(INTERVALS_ARTIFACT)?;
+            (extracted_path, Some(intervals))
+        }
+        Some(reference) => {
+            progress("Extracting sequences", 0.0);
+            let (extracted_path, intervals) = extract_bed_sequences(fasta_path, reference, form, result_path)?;
+            checkpoint.save_artifact(INTERVALS_ARTIFACT, &intervals)?;
+            checkpoint.mark_complete(PipelineStage::ExtractSequences)?;
+            (extracted_path, Some(intervals))
+        }
+        None => (fasta_path_str.to_string(), None),
+    };
+
+    check_cancelled(cancelled)?;
+    progress("Parsing sequences", 5.0);
+    tracing::debug!("Loading FASTA file: {}", input_fasta);
 
     // Load FASTA file and convert to sequence vector
-    let sequences = load_fasta(fasta_path_str);
+    let (sequence_ids, sequences): (Vec<String>, Vec<Vec<u8>>) =
+        load_fasta(&input_fasta).into_iter().unzip();
 
     // Calculate k-mers
-    let kmer_length = 8;
-    tracing::debug!("Calculating {}-mers", kmer_length);
-    let kmer_counts = count_kmers_in_sequences(&sequences, kmer_length, false);
-    
-    // Convert HashMap to vector for sorting
-    let mut kmer_counts_vec: Vec<_> = kmer_counts.into_iter().collect();
-    // Sort by count in descending order
-    kmer_counts_vec.sort_by(|a, b| b.1.cmp(&a.1));  
-    
+    let kmer_length = KMER_LENGTH;
+    let alphabet = form.alphabet;
+    if kmer_length > alphabet.max_kmer_length() {
+        return Err(WorkerError::InvalidKmer(format!(
+            "{}-mers do not fit the packed {:?} alphabet (at most {})",
+            kmer_length,
+            alphabet,
+            alphabet.max_kmer_length()
+        )));
+    }
+
+    // Get result path as string with proper error handling
+    let result_path_str = result_path.to_str()
+        .ok_or_else(|| {
+            tracing::error!("Invalid UTF-8 in result path: {}", result_path.display());
+            WorkerError::Processing(format!(
+                "Invalid UTF-8 in result path: {}", 
+                result_path.display()
+            ))
+        })?;
+
+    let kmer_counts_vec: Vec<(u64, u32)> = if checkpoint.is_complete(PipelineStage::CountKmers) {
+        checkpoint.load_artifact(KMER_COUNTS_ARTIFACT)?
+    } else {
+        check_cancelled(cancelled)?;
+        progress("Counting k-mers", 10.0);
+        tracing::debug!("Calculating {}-mers over the {:?} alphabet", kmer_length, alphabet);
+        // In revcom mode a k-mer and its reverse complement are counted together
+        let revcom_mode = form.revcom_mode && alphabet.supports_revcom();
+        let kmer_counts = count_kmers_in_sequences(&sequences, kmer_length, alphabet, revcom_mode);
+
+        // Convert HashMap to vector for sorting
+        let mut kmer_counts_vec: Vec<_> = kmer_counts.into_iter().collect();
+        // Sort by count in descending order, ties by k-mer so reruns give identical output
+        kmer_counts_vec.sort_by_key(|&(kmer, count)| (std::cmp::Reverse(count), kmer));
+
+        // Save results to file
+        check_cancelled(cancelled)?;
+        progress("Saving k-mer counts", 18.0);
+        tracing::debug!("Saving results to file: {}", result_path_str);
+        save_results_to_file(&kmer_counts_vec, kmer_length, alphabet, result_path_str)?;
+        checkpoint.save_artifact(KMER_COUNTS_ARTIFACT, &kmer_counts_vec)?;
+        checkpoint.mark_complete(PipelineStage::CountKmers)?;
+        kmer_counts_vec
+    };
+
     tracing::debug!("Converting top {} k-mers to strings", 10);
     let result: HashMap<String, u32> = kmer_counts_vec.iter()
         .take(10)
         .map(|(kmer, count)| {
-            let kmer_string = String::from_utf8(hash2kmer(*kmer, kmer_length))
+            let kmer_string = String::from_utf8(hash2kmer(*kmer, kmer_length, alphabet))
                 .map_err(|e| {
                     tracing::error!("Invalid UTF-8 in k-mer conversion: {}", e);
                     WorkerError::InvalidKmer(format!(
@@ -217,27 +1103,98 @@ async fn process_task(
         })
         .collect::<Result<HashMap<String, u32>, WorkerError>>()?;
 
-    // Get result path as string with proper error handling
-    let result_path_str = result_path.to_str()
-        .ok_or_else(|| {
-            tracing::error!("Invalid UTF-8 in result path: {}", result_path.display());
-            WorkerError::Processing(format!(
-                "Invalid UTF-8 in result path: {}", 
-                result_path.display()
-            ))
-        })?;
+    // Build seed motifs from the top k-mers and optionally refine them with EM
+    let mut motif_results: MotifResults = if checkpoint.is_complete(PipelineStage::DiscoverMotifs) {
+        checkpoint.load_artifact(MOTIFS_ARTIFACT)?
+    } else {
+        tracing::debug!("Discovering top {} motifs (EM refinement: {})", form.top_k, form.em_refine);
+        let motif_results = discover_motifs(
+            &sequences,
+            &kmer_counts_vec,
+            kmer_length,
+            form,
+            cancelled,
+            progress,
+        )?;
+        checkpoint.save_artifact(MOTIFS_ARTIFACT, &motif_results)?;
+        checkpoint.mark_complete(PipelineStage::DiscoverMotifs)?;
+        motif_results
+    };
+
+    // Group related motifs into families
+    if !checkpoint.is_complete(PipelineStage::ClusterMotifs) {
+        check_cancelled(cancelled)?;
+        progress("Clustering motifs", 85.0);
+        cluster_discovered_motifs(&mut motif_results, result_path_str)?;
+        save_motifs_to_file(&motif_results, result_path_str)?;
+        checkpoint.mark_complete(PipelineStage::ClusterMotifs)?;
+    }
 
-    // Save results to file
-    tracing::debug!("Saving results to file: {}", result_path_str);
-    save_results_to_file(&kmer_counts_vec, kmer_length, result_path_str)?;
+    // Locate the best site of every motif in each sequence
+    if !checkpoint.is_complete(PipelineStage::ScanSites) {
+        check_cancelled(cancelled)?;
+        progress("Scanning sites", 92.0);
+        save_sites_to_file(
+            &motif_results,
+            &sequences,
+            &sequence_ids,
+            intervals.as_deref(),
+            result_path_str,
+        )?;
+        checkpoint.mark_complete(PipelineStage::ScanSites)?;
+    }
 
+    progress("Finished", 100.0);
     tracing::info!("Successfully processed task for file: {}", fasta_path_str);
     Ok(result)
 }
 
+// Helper function to extract the sequences under a BED file's intervals
+// Writes them to extracted_sequences.fa in the result directory and returns its path
+// together with the windows actually extracted, in FASTA order
+fn extract_bed_sequences(
+    bed_path: &Path,
+    reference_fasta: &Path,
+    form: &ProcessForm,
+    result_path: &Path,
+) -> WorkerResult<(String, Vec<GenomicInterval>)> {
+    if !reference_fasta.exists() {
+        tracing::error!("Reference FASTA not found: {}", reference_fasta.display());
+        return Err(WorkerError::FileNotFound(reference_fasta.display().to_string()));
+    }
+
+    let intervals = parse_bed(bed_path)
+        .map_err(|e| {
+            tracing::error!("Failed to parse BED file {}: {}", bed_path.display(), e);
+            WorkerError::Processing(format!("Invalid BED file: {}", e))
+        })?;
+    tracing::debug!("Parsed {} intervals from {}", intervals.len(), bed_path.display());
+
+    let options = WindowOptions {
+        recenter: form.recenter,
+        window_width: form.window_width as u64,
+    };
+    let output_path = result_path.join("extracted_sequences.fa");
+    let windows = extract_sequences(&intervals, reference_fasta, options, &output_path)
+        .map_err(|e| {
+            tracing::error!("Failed to extract sequences from {}: {}", reference_fasta.display(), e);
+            WorkerError::Processing(format!("Failed to extract sequences: {}", e))
+        })?;
+    tracing::info!("Extracted {} of {} intervals", windows.len(), intervals.len());
+
+    let output_path = output_path.to_str()
+        .ok_or_else(|| WorkerError::Processing(format!(
+            "Invalid UTF-8 in result path: {}",
+            output_path.display()
+        )))?
+        .to_string();
+    Ok((output_path, windows))
+}
+
 fn save_results_to_file(
     kmer_counts_vec: &[(u64, u32)],
     kmer_length: usize,
+    alphabet: Alphabet,
     result_path: &str
 ) -> WorkerResult<()> {
     let output_path = Path::new(result_path).join("top10kmers.txt");
@@ -260,7 +1217,7 @@ fn save_results_to_file(
 
     // Write k-mer counts
     for (kmer, count) in kmer_counts_vec.iter().take(10) {
-        let kmer_string = String::from_utf8(hash2kmer(*kmer, kmer_length))
+        let kmer_string = String::from_utf8(hash2kmer(*kmer, kmer_length, alphabet))
             .map_err(|e| {
                 tracing::error!("Invalid UTF-8 in k-mer: {}", e);
                 WorkerError::InvalidKmer(format!("Failed to convert k-mer hash {} to string", kmer))
@@ -279,6 +1236,317 @@ fn save_results_to_file(
     Ok(())
 }
 
+// Helper function to build Hamming-ball seed PWMs for the top_k k-mers
+// Each seed is refined with ZOOPS EM when the form asks for it
+fn discover_motifs(
+    sequences: &[Vec<u8>],
+    kmer_counts_vec: &[(u64, u32)],
+    kmer_length: usize,
+    form: &ProcessForm,
+    cancelled: &AtomicBool,
+    progress: &dyn Fn(&str, f64),
+) -> WorkerResult<MotifResults> {
+    let alphabet = form.alphabet;
+    let max_ham_dist = motif_def(kmer_length, alphabet.size())
+        .map(|def| def.max_ham_dist)
+        .ok_or_else(|| WorkerError::InvalidKmer(format!(
+            "No motif definition for {}-mers",
+            kmer_length
+        )))?;
+
+    // Reverse complement only applies to DNA
+    let revcom_mode = form.revcom_mode && alphabet.supports_revcom();
+    if form.revcom_mode && !revcom_mode {
+        tracing::debug!("Ignoring revcom_mode for the {:?} alphabet", alphabet);
+    }
+
+    let em_input = EmInput::new(sequences, alphabet);
+
+    // Motif discovery spans 20% to 85% of the whole pipeline
+    let motif_count = kmer_counts_vec.len().min(form.top_k as usize).max(1);
+    let motifs = kmer_counts_vec.iter()
+        .take(form.top_k as usize)
+        .enumerate()
+        .map(|(index, &(seed, seed_count))| {
+            check_cancelled(cancelled)?;
+            progress("Discovering motifs", 20.0 + 65.0 * index as f64 / motif_count as f64);
+            let seed_string = String::from_utf8(hash2kmer(seed, kmer_length, alphabet))
+                .map_err(|_| WorkerError::InvalidKmer(format!(
+                    "Failed to convert k-mer hash {} to string",
+                    seed
+                )))?;
+
+            let seed_pwm = hamming_ball_pwm(
+                seed,
+                kmer_length,
+                max_ham_dist,
+                kmer_counts_vec,
+                alphabet,
+                revcom_mode,
+            );
+
+            let refinement = form.em_refine.then(|| {
+                refine_zoops(&em_input, &seed_pwm, form.em_max_iter, revcom_mode)
+            });
+            if let Some(em) = &refinement {
+                tracing::debug!(
+                    "Refined seed {} in {} iterations: log-likelihood +{:.3}, {} sites",
+                    seed_string,
+                    em.iterations,
+                    em.log_likelihood_improvement,
+                    em.sites
+                );
+            }
+
+            let mut motif = Motif {
+                seed: seed_string,
+                seed_count,
+                consensus: String::new(),
+                seed_pwm,
+                refinement,
+            };
+            motif.consensus = motif.final_pwm().consensus();
+            Ok(motif)
+        })
+        .collect::<Result<Vec<Motif>, WorkerError>>()?;
+
+    Ok(MotifResults {
+        alphabet,
+        kmer_length,
+        revcom_mode,
+        background: em_input.background().to_vec(),
+        motifs,
+        clustering: None,
+    })
+}
+
+// Helper function to cluster motifs into families
+// Writes the dendrogram as motif_tree.nwk and renders it with logos to motif_tree.svg
+fn cluster_discovered_motifs(motif_results: &mut MotifResults, result_path: &str) -> WorkerResult<()> {
+    if motif_results.motifs.len() < 2 {
+        tracing::debug!("Skipping motif clustering for fewer than two motifs");
+        return Ok(());
+    }
+
+    let pwms: Vec<_> = motif_results.motifs.iter().map(|motif| motif.final_pwm()).collect();
+    let labels: Vec<String> = motif_results.motifs.iter()
+        .enumerate()
+        .map(|(i, motif)| motif.label(i + 1))
+        .collect();
+
+    let clustering = cluster_motifs(
+        &pwms,
+        &labels,
+        motif_results.revcom_mode,
+        DEFAULT_MAX_CLUSTER_DISTANCE,
+    );
+    tracing::debug!(
+        "Clustered {} motifs into {} families",
+        labels.len(),
+        clustering.clusters.len()
+    );
+
+    let newick_path = Path::new(result_path).join("motif_tree.nwk");
+    std::fs::write(&newick_path, format!("{}\n", clustering.newick))
+        .map_err(|e| {
+            tracing::error!("Failed to write {}: {}", newick_path.display(), e);
+            WorkerError::Io(e)
+        })?;
+
+    let plot_path = Path::new(result_path).join("motif_tree.svg");
+    plot_motif_tree(&plot_path, &clustering.dendrogram, &pwms, &labels)
+        .map_err(|e| {
+            tracing::error!("Failed to plot motif tree: {}", e);
+            WorkerError::Processing(format!("Failed to plot motif tree: {}", e))
+        })?;
+
+    motif_results.clustering = Some(clustering);
+    Ok(())
+}
+
+// Helper function to write discovered motifs
+// results.json holds the structured results, motifs.meme the final PWMs in MEME format
+fn save_motifs_to_file(motif_results: &MotifResults, result_path: &str) -> WorkerResult<()> {
+    let json_path = Path::new(result_path).join("results.json");
+    let json_file = File::create(&json_path)
+        .map_err(|e| {
+            tracing::error!("Failed to create file {}: {}", json_path.display(), e);
+            WorkerError::Io(e)
+        })?;
+    serde_json::to_writer_pretty(json_file, motif_results)
+        .map_err(|e| {
+            tracing::error!("Failed to write structured results: {}", e);
+            WorkerError::Processing(format!("Failed to write structured results: {}", e))
+        })?;
+
+    let meme_path = Path::new(result_path).join("motifs.meme");
+    let mut file = File::create(&meme_path)
+        .map_err(|e| {
+            tracing::error!("Failed to create file {}: {}", meme_path.display(), e);
+            WorkerError::Io(e)
+        })?;
+
+    let alphabet = motif_results.alphabet;
+    let background = alphabet.letters().iter()
+        .zip(&motif_results.background)
+        .map(|(letter, freq)| format!("{} {:.4}", *letter as char, freq))
+        .collect::<Vec<_>>()
+        .join(" ");
+    writeln!(file, "MEME version 4\n\nALPHABET= {}\n", alphabet.meme_name())?;
+    if alphabet.supports_revcom() {
+        let strands = if motif_results.revcom_mode { "+ -" } else { "+" };
+        writeln!(file, "strands: {}\n", strands)?;
+    }
+    writeln!(file, "Background letter frequencies\n{}\n", background)?;
+
+    for motif in &motif_results.motifs {
+        let pwm = motif.final_pwm();
+        let nsites = motif.refinement.as_ref().map_or(motif.seed_count as usize, |em| em.sites);
+        writeln!(file, "MOTIF {} seed_{}", motif.consensus, motif.seed)?;
+        writeln!(
+            file,
+            "letter-probability matrix: alength= {} w= {} nsites= {} E= 0",
+            alphabet.size(),
+            pwm.width(),
+            nsites
+        )?;
+        for row in &pwm.rows {
+            let row: Vec<String> = row.iter().map(|p| format!("{:.6}", p)).collect();
+            writeln!(file, " {}", row.join(" "))?;
+        }
+        writeln!(file)?;
+    }
+
+    tracing::info!("Successfully saved {} motifs to {}", motif_results.motifs.len(), result_path);
+    Ok(())
+}
+
+// Helper function to write the best site of each motif per sequence to motif_sites.tsv
+// Sequences extracted from BED intervals also get the site's genomic coordinates
+fn save_sites_to_file(
+    motif_results: &MotifResults,
+    sequences: &[Vec<u8>],
+    sequence_ids: &[String],
+    intervals: Option<&[GenomicInterval]>,
+    result_path: &str,
+) -> WorkerResult<()> {
+    let output_path = Path::new(result_path).join("motif_sites.tsv");
+    let mut file = File::create(&output_path)
+        .map_err(|e| {
+            tracing::error!("Failed to create file {}: {}", output_path.display(), e);
+            WorkerError::Io(e)
+        })?;
+
+    writeln!(
+        file,
+        "motif\tsequence\tstart\tend\tstrand\tscore\trelative_score\tchrom\tgenomic_start\tgenomic_end\tgenomic_strand"
+    )?;
+
+    let stranded = motif_results.alphabet.supports_revcom();
+    let strand = |reverse: bool| match (stranded, reverse) {
+        (false, _) => '.',
+        (true, false) => '+',
+        (true, true) => '-',
+    };
+
+    for (rank, motif) in motif_results.motifs.iter().enumerate() {
+        let pwm = motif.final_pwm();
+        let sites = scan_best_sites(
+            pwm,
+            &motif_results.background,
+            sequences,
+            motif_results.revcom_mode,
+            DEFAULT_MIN_RELATIVE_SCORE,
+        );
+
+        for site in sites {
+            let end = site.offset + pwm.width();
+            let genomic = match intervals.and_then(|intervals| intervals.get(site.sequence)) {
+                Some(interval) => {
+                    let (start, end) = interval.site_coordinates(site.offset as u64, pwm.width() as u64);
+                    format!(
+                        "{}\t{}\t{}\t{}",
+                        interval.chrom,
+                        start,
+                        end,
+                        strand(site.reverse != interval.reverse)
+                    )
+                }
+                None => ".\t.\t.\t.".to_string(),
+            };
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}",
+                motif.label(rank + 1),
+                sequence_ids[site.sequence],
+                site.offset,
+                end,
+                strand(site.reverse),
+                site.score,
+                site.relative_score,
+                genomic
+            )?;
+        }
+    }
+
+    tracing::info!("Successfully saved motif sites to {}", output_path.display());
+    Ok(())
+}
+
+// Helper function to record the work a completed task did against its run time
+// BED tasks are measured by the sequences extracted for them
+async fn record_runtime_sample(redis_service: &RedisService, task: &TaskInfo, seconds: u64) {
+    let sequence_path = match task.reference_fasta {
+        Some(_) => Path::new(&task.result_path).join("extracted_sequences.fa"),
+        None => Path::new(&task.fasta_path).to_path_buf(),
+    };
+    let sequence_bytes = match tokio::fs::metadata(&sequence_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            tracing::warn!("Failed to measure input of task {}: {}", task.task_id, e);
+            return;
+        }
+    };
+
+    let sample = RuntimeSample {
+        work_units: estimator::work_units(sequence_bytes, &task.params),
+        seconds,
+    };
+    if let Err(e) = redis_service.record_runtime_sample(&sample).await {
+        tracing::warn!("Failed to record run time sample of task {}: {}", task.task_id, e);
+    }
+}
+
+// Helper function to mark a dequeued task as Processing, count the attempt and reserve its quota
+async fn start_task(
+    redis_service: &RedisService,
+    config: &Config,
+    task_id: &str,
+) -> WorkerResult<TaskInfo> {
+    let mut task = redis_service
+        .get_task(task_id)
+        .await
+        .map_err(WorkerError::Redis)?
+        .ok_or_else(|| WorkerError::Processing(format!("Task {} not found", task_id)))?;
+
+    task.reserved_quota = reserve_quota(redis_service, config, &task.user).await?;
+    task.attempts += 1;
+    task.status = TaskStatus::Processing;
+    task.start_time = Some(Utc::now());
+    task.error = None;
+    if let Err(e) = redis_service.save_task(&task).await {
+        tracing::error!("Failed to save task {}: {}", task_id, e);
+        // The stored task does not carry the reservation, so nothing else would release it
+        if let Err(e) = settle_quota(redis_service, &mut task, 0).await {
+            tracing::error!("Failed to release quota reserved for task {}: {}", task_id, e);
+        }
+        return Err(WorkerError::Redis(e));
+    }
+
+    tracing::info!("Started task {} (attempt {})", task_id, task.attempts);
+    Ok(task)
+}
+
 pub async fn update_task_status(
     redis_service: &RedisService,
     task_id: &str,
@@ -320,7 +1588,8 @@ pub async fn update_task_result_user_quota(
     task_id: &str,
     status: TaskStatus,
     result: Option<HashMap<String, u32>>,
-) -> WorkerResult<()> {
+    error: Option<TaskError>,
+) -> WorkerResult<TaskInfo> {
     // Get task with proper error handling
     let mut task = redis_service
         .get_task(task_id)
@@ -338,38 +1607,28 @@ pub async fn update_task_result_user_quota(
     let status_for_logging = status.clone();  // Store status for logging
     task.status = status;
     task.result = result;
+    task.error = error;
 
-    // Update completion time and user quota for completed or failed tasks
-    if matches!(task.status, TaskStatus::Completed | TaskStatus::Failed) {
-        task.completion_time = Some(Utc::now());
+    // Update completion time and user quota for finished tasks
+    if matches!(task.status, TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Cancelled) {
+        let completion_time = Utc::now();
+        task.completion_time = Some(completion_time);
 
-        // Get user data for quota update
-        let mut user = redis_service
-            .get_user(&task.user)
-            .await
-            .map_err(WorkerError::Redis)?
-            .ok_or_else(|| WorkerError::Processing(format!("User {} not found", task.user)))?;
-
-        // Calculate and update quota usage
-        if let Some(completion_time) = task.completion_time {
-            let duration = completion_time.signed_duration_since(task.submission_time);
-            let seconds = duration.num_seconds() as u64;
-            user.used_quota += seconds;
-
-            // Save updated user data
-            redis_service
-                .save_user(&user)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Failed to update quota for user {}: {}", task.user, e);
-                    WorkerError::Redis(e)
-                })?;
+        // Charge the worker's wall time since it started the task, never time spent queued
+        let seconds_used = task.start_time.map_or(0, |start_time| {
+            completion_time.signed_duration_since(start_time).num_seconds().max(0) as u64
+        });
+        settle_quota(redis_service, &mut task, seconds_used).await?;
+        charge_storage(redis_service, &mut task).await?;
+        release_active_task(redis_service, &task).await?;
 
-            tracing::info!(
-                "Updated quota for user {}: {} seconds used",
-                task.user,
-                seconds
-            );
+        // Completed run times feed the start time estimates of queued tasks
+        // and calibrate the run time estimates shown before submission
+        if matches!(task.status, TaskStatus::Completed) {
+            if let Err(e) = redis_service.record_task_duration(seconds_used).await {
+                tracing::warn!("Failed to record duration of task {}: {}", task_id, e);
+            }
+            record_runtime_sample(redis_service, &task, seconds_used).await;
         }
     }
 
@@ -387,5 +1646,5 @@ pub async fn update_task_result_user_quota(
         task_id,
         status_for_logging
     );
-    Ok(())
+    Ok(task)
 }
\ No newline at end of file